@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+
+use crate::remote::{AuthMethod, Privilege};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "A tool to install nixos configuration with sops keys update"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    #[command(flatten)]
+    pub remote: RemoteArgs,
+
+    #[command(flatten)]
+    pub flake: FlakeArgs,
+
+    /// Only report what would be done, without touching anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Replay answers from a YAML/JSON answers file keyed by question id
+    #[arg(long, global = true)]
+    pub answers: Option<PathBuf>,
+
+    /// Record every answer into a YAML/JSON answers file
+    #[arg(long, global = true)]
+    pub record: Option<PathBuf>,
+
+    /// Age key file or OpenSSH private key decrypting the sops data keys when rekeying
+    #[arg(long, global = true)]
+    pub sops_identity: Option<PathBuf>,
+
+    /// known_hosts file to verify and record host keys in (default to ~/.ssh/known_hosts)
+    #[arg(long, global = true)]
+    pub known_hosts: Option<PathBuf>,
+
+    /// Never prompt: use default answers and abort on missing ones
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the whole bootstrap (installer image, secrets and final deployment)
+    Bootstrap(BootstrapArgs),
+    /// Fetch the remote hardware configuration into the flake
+    Hardware,
+    /// Select the remote disk device and write it into the flake
+    Disk,
+    /// Add the remote age key into sops and rekey encrypted files
+    Rekey {
+        /// Remove the config host key from sops instead of adding it
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Deploy the flake with nixos-anywhere
+    Deploy(DeployArgs),
+    /// Deploy the flake with nixos-rebuild
+    Rebuild,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct BootstrapArgs {
+    /// Remote host system is running on an installer image
+    #[arg(long)]
+    pub installer: Option<bool>,
+
+    /// Config host deployed on the installer image (plankton*)
+    #[arg(long)]
+    pub installer_host: Option<String>,
+
+    /// Skip the steps already completed by a previous run
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Path of the bootstrap state file
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DeployArgs {
+    /// Generate the ssh host key locally and add its age key into sops before installing
+    #[arg(long)]
+    pub pregenerate_host_key: bool,
+
+    /// Back up the current ssh host keys into the flake (sops encrypted) and restore them
+    #[arg(long, conflicts_with = "pregenerate_host_key")]
+    pub keep_host_keys: bool,
+
+    /// Impermanence root the extra files are installed under (e.g. /persist)
+    #[arg(long)]
+    pub persist_path: Option<String>,
+}
+
+#[derive(Args, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteArgs {
+    /// SSH destination of the remote host
+    #[arg(short, long, global = true)]
+    pub destination: Option<String>,
+
+    /// SSH port of the remote host
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    pub port: Option<u16>,
+
+    /// SSH user of the remote host
+    #[arg(short, long, global = true)]
+    pub user: Option<String>,
+
+    /// SSH authentication method (agent, password, identity-file)
+    #[arg(short, long, global = true)]
+    pub auth: Option<AuthMethod>,
+
+    /// SSH identity file to authenticate with, also given to the spawned deployment commands
+    #[arg(short, long, global = true)]
+    pub identity_file: Option<String>,
+
+    /// How commands needing root run on the remote host (none, sudo, sudo-password, doas)
+    #[arg(long, global = true)]
+    pub privilege: Option<Privilege>,
+
+    /// Expected host key fingerprint (SHA256:...), any other key aborts the connection
+    #[arg(long, global = true)]
+    // Not kept in the bootstrap state, the pinned key is the installer's until the install
+    #[serde(skip)]
+    pub expect_fingerprint: Option<String>,
+
+    /// Jump hosts to reach the remote host through ([user@]host[:port], comma separated)
+    #[arg(short = 'J', long, global = true)]
+    pub jump: Option<String>,
+
+    /// Seconds to wait for the ssh connection and handshake (default 10)
+    #[arg(long, global = true)]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds a remote command may run (default no limit)
+    #[arg(long, global = true)]
+    pub command_timeout: Option<u64>,
+
+    /// Seconds between ssh keepalives (default 15, 0 disables them)
+    #[arg(long, global = true)]
+    pub keepalive: Option<u32>,
+
+    /// Public key authorized for the user and root after a password login (`agent` or a key file)
+    #[arg(long, global = true)]
+    pub install_key: Option<String>,
+
+    /// Block device to install on (e.g. sda, nvme0n1)
+    #[arg(long, global = true)]
+    pub disk: Option<String>,
+}
+
+impl RemoteArgs {
+    pub fn fill(&mut self, defaults: &RemoteArgs) {
+        if self.destination.is_none() {
+            self.destination = defaults.destination.clone();
+        }
+        if self.port.is_none() {
+            self.port = defaults.port;
+        }
+        if self.user.is_none() {
+            self.user = defaults.user.clone();
+        }
+        if self.auth.is_none() {
+            self.auth = defaults.auth.clone();
+        }
+        if self.identity_file.is_none() {
+            self.identity_file = defaults.identity_file.clone();
+        }
+        if self.privilege.is_none() {
+            self.privilege = defaults.privilege.clone();
+        }
+        if self.expect_fingerprint.is_none() {
+            self.expect_fingerprint = defaults.expect_fingerprint.clone();
+        }
+        if self.jump.is_none() {
+            self.jump = defaults.jump.clone();
+        }
+        if self.connect_timeout.is_none() {
+            self.connect_timeout = defaults.connect_timeout;
+        }
+        if self.command_timeout.is_none() {
+            self.command_timeout = defaults.command_timeout;
+        }
+        if self.keepalive.is_none() {
+            self.keepalive = defaults.keepalive;
+        }
+        if self.install_key.is_none() {
+            self.install_key = defaults.install_key.clone();
+        }
+        if self.disk.is_none() {
+            self.disk = defaults.disk.clone();
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct FlakeArgs {
+    /// Path of the local nix-config flake
+    #[arg(short = 'c', long, visible_alias = "config", global = true)]
+    pub flake: Option<PathBuf>,
+
+    /// Config host of the flake (nixosConfigurations)
+    #[arg(short = 'n', long, global = true)]
+    pub host: Option<String>,
+}
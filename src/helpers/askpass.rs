@@ -0,0 +1,162 @@
+use std::{
+    env,
+    io::{Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{Context, Result, bail};
+use tempfile::{TempDir, tempdir};
+use tracing::{info, warn};
+
+use crate::helpers::prompt;
+
+/// Socket the helper asks its answers to, set in the environment of spawned commands.
+const SOCKET_ENV: &str = "NIX_BOOTSTRAP_ASKPASS";
+
+/// Environment variables of a spawned command.
+pub type Envs = Vec<(String, String)>;
+
+/// Secrets typed during authentication that spawned ssh commands may ask for again.
+#[derive(Default)]
+pub struct Secrets {
+    /// `user@host` names the password is given for
+    pub logins: Vec<String>,
+    pub password: Option<String>,
+    /// File name of the identity file the passphrase unlocks
+    pub identity_file: Option<String>,
+    pub passphrase: Option<String>,
+}
+
+impl Secrets {
+    fn is_empty(&self) -> bool {
+        self.password.is_none() && self.passphrase.is_none()
+    }
+
+    /// The known secret matching an ssh prompt.
+    fn answer(&self, prompt: &str) -> Option<&str> {
+        let lower = prompt.to_lowercase();
+        if lower.contains("passphrase") {
+            let identity_file = self.identity_file.as_deref()?;
+            return prompt
+                .contains(identity_file)
+                .then_some(self.passphrase.as_deref())
+                .flatten();
+        }
+        if lower.contains("password") && self.logins.iter().any(|login| prompt.contains(login)) {
+            return self.password.as_deref();
+        }
+        None
+    }
+}
+
+/// Answers the prompts of spawned ssh commands (`SSH_ASKPASS`) until dropped. Known secrets
+/// are given back, other prompts are asked to the operator.
+pub struct Server {
+    dir: TempDir,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// None when there is no secret to give back.
+    pub fn start(secrets: Secrets) -> Result<Option<Self>> {
+        if secrets.is_empty() {
+            return Ok(None);
+        }
+        // Only the current user can reach the socket of a 0700 directory
+        let dir = tempdir().context("Failed to create temp directory")?;
+        let listener = UnixListener::bind(dir.path().join("askpass"))
+            .context("Creating askpass socket failed")?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Err(err) = stream.map_err(anyhow::Error::from).and_then(|mut stream| {
+                    let mut prompt = String::new();
+                    stream.read_to_string(&mut prompt)?;
+                    if let Some(answer) = reply(&secrets, prompt.trim())? {
+                        stream.write_all(format!("+{answer}").as_bytes())?;
+                    }
+                    Ok(())
+                }) {
+                    warn!("❗ Answering askpass failed: {err}");
+                }
+            }
+        });
+        info!("🔸 Spawned ssh commands reuse the credentials of the session");
+        Ok(Some(Self {
+            dir,
+            stop,
+            thread: Some(thread),
+        }))
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.path().join("askpass")
+    }
+
+    /// Environment making ssh ask this process instead of the terminal.
+    pub fn envs(&self) -> Result<Envs> {
+        let exe = env::current_exe().context("Locating nix-bootstrap executable failed")?;
+        Ok(vec![
+            ("SSH_ASKPASS".to_string(), exe.display().to_string()),
+            ("SSH_ASKPASS_REQUIRE".to_string(), "force".to_string()),
+            (SOCKET_ENV.to_string(), self.socket().display().to_string()),
+        ])
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the accept loop up so that it sees the stop flag
+        let _ = UnixStream::connect(self.socket());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answer of an ssh prompt, None refuses it.
+fn reply(secrets: &Secrets, text: &str) -> Result<Option<String>> {
+    if let Some(answer) = secrets.answer(text) {
+        return Ok(Some(answer.to_string()));
+    }
+    // Host key confirmations expect `yes` or `no`
+    if text.contains("(yes/no") {
+        let yes = prompt::confirm("askpass-confirm", text, Some(false))?;
+        return Ok(Some(if yes { "yes" } else { "no" }.to_string()));
+    }
+    prompt::password("askpass", text).map(Some)
+}
+
+/// Act as `SSH_ASKPASS` when started by a spawned ssh: print the answer to the prompt given
+/// as argument. Returns false when not started as a helper.
+pub fn run_helper() -> Result<bool> {
+    let Some(socket) = env::var_os(SOCKET_ENV) else {
+        return Ok(false);
+    };
+    let prompt = env::args().nth(1).unwrap_or_default();
+    let mut stream = UnixStream::connect(&socket).context("Connecting to askpass socket failed")?;
+    stream.write_all(prompt.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    match reply.strip_prefix('+') {
+        Some(answer) => {
+            println!("{answer}");
+            Ok(true)
+        }
+        None => bail!("No answer to {}", prompt.trim()),
+    }
+}
@@ -1,11 +1,17 @@
-use std::process::Command;
+use std::{path::Path, process::Command};
 
 use anyhow::{Context, Result, bail};
 
-pub fn run(cmd: &str) -> Result<()> {
+/// Quote `arg` for a POSIX shell.
+pub fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+pub fn run(cmd: &str, envs: &[(String, String)]) -> Result<()> {
     let output = Command::new("sh")
         .arg("-c")
         .arg(cmd)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
         .output()
         .context("failed to run command")?;
 
@@ -35,3 +41,49 @@ pub fn run_with_stdout(cmd: &str) -> Result<String> {
     }
     Ok(stdout)
 }
+
+pub fn run_program(
+    dir: &Path,
+    program: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .output()
+        .context(format!("failed to run {program}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Command failed ({:?}):\n{}", output.status, stderr)
+    }
+    Ok(())
+}
+
+pub fn run_program_with_stdout(
+    dir: &Path,
+    program: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .envs(envs.iter().map(|(key, value)| (key, value)))
+        .output()
+        .context(format!("failed to run {program}"))?;
+
+    let stdout = String::from_utf8(output.stdout)?;
+    let stderr = String::from_utf8(output.stderr)?;
+
+    if !output.status.success() {
+        bail!(
+            "Command fail with exit status ({:?}) and stderr: \n{}",
+            output.status,
+            stderr
+        )
+    }
+    Ok(stdout)
+}
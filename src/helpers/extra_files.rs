@@ -0,0 +1,50 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tempfile::{TempDir, tempdir};
+
+/// Directory tree copied onto the target root by nixos-anywhere `--extra-files`.
+pub struct ExtraFiles {
+    dir: TempDir,
+    prefix: PathBuf,
+}
+
+impl ExtraFiles {
+    /// Files are placed under `persist_path` (e.g. `/persist`) on impermanence setups.
+    pub fn new(persist_path: Option<&str>) -> Result<Self> {
+        let dir = tempdir().context("Failed to create temp directory")?;
+        // The tree root is copied over the target root, which must stay world readable
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755))
+            .context("Setting permissions of temp directory failed")?;
+        Ok(Self {
+            dir,
+            prefix: PathBuf::from(persist_path.unwrap_or_default().trim_start_matches('/')),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Add a file at the absolute target `path` with the unix `mode`.
+    pub fn add(&self, path: &str, contents: &[u8], mode: u32) -> Result<()> {
+        let file_path = self
+            .dir
+            .path()
+            .join(&self.prefix)
+            .join(path.trim_start_matches('/'));
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent).context(format!("Creating {} failed", parent.display()))?;
+        }
+        fs::write(&file_path, contents)
+            .context(format!("Writing {} failed", file_path.display()))?;
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(mode)).context(format!(
+            "Setting permissions of {} failed",
+            file_path.display()
+        ))
+    }
+}
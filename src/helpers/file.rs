@@ -0,0 +1,41 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use tracing::info;
+
+pub fn write(path: &Path, contents: &[u8], dry_run: bool) -> Result<()> {
+    if !dry_run {
+        return fs::write(path, contents).context(format!("Writing {} failed", path.display()));
+    }
+
+    let old = fs::read(path).unwrap_or_default();
+    let old = String::from_utf8_lossy(&old);
+    let new = String::from_utf8_lossy(contents);
+    if old == new {
+        info!("🔸 [dry-run] {} would be left unchanged", path.display());
+        return Ok(());
+    }
+    let name = path.display().to_string();
+    let diff = TextDiff::from_lines(old.as_ref(), new.as_ref())
+        .unified_diff()
+        .header(&name, &name)
+        .to_string();
+    info!(
+        "🔸 [dry-run] {} would be changed:\n{}",
+        path.display(),
+        diff
+    );
+    Ok(())
+}
+
+/// Expand a leading `~/` into the local home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs2::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
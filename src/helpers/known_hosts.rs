@@ -0,0 +1,392 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, anyhow};
+use base64ct::{Base64, Encoding};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use ssh_key::rand_core::{OsRng, RngCore};
+
+use super::ssh_config;
+
+/// OpenSSH `known_hosts` file, kept line by line so untouched entries are written back as-is.
+pub struct KnownHosts {
+    path: PathBuf,
+    lines: Vec<Line>,
+}
+
+enum Line {
+    Entry(Entry),
+    /// Comment, blank or unparsable line
+    Other(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+struct Entry {
+    marker: Option<Marker>,
+    /// Comma separated patterns, or a single `|1|salt|hash` hashed host
+    hosts: String,
+    key_type: String,
+    key: String,
+    comment: Option<String>,
+    /// Line as read, `None` once the entry has been changed
+    raw: Option<String>,
+}
+
+/// Public key of an OpenSSH line (`<type> <base64> [comment]`).
+#[derive(Clone, PartialEq, Debug)]
+pub struct Key {
+    pub key_type: String,
+    pub key: String,
+}
+
+impl Key {
+    pub fn parse(openssh: &str) -> Result<Self> {
+        let mut fields = openssh.split_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some(key_type), Some(key)) => Ok(Self {
+                key_type: key_type.to_string(),
+                key: key.to_string(),
+            }),
+            _ => Err(anyhow!("Invalid public key {openssh}")),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.key_type, self.key)
+    }
+}
+
+/// Name of a host in known_hosts: bare for port 22, `[host]:port` otherwise.
+pub fn host_pattern(host: &str, port: u16) -> String {
+    match port {
+        22 => host.to_string(),
+        port => format!("[{host}]:{port}"),
+    }
+}
+
+impl KnownHosts {
+    /// Load a known_hosts file, empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match path.exists() {
+            true => {
+                fs::read_to_string(path).context(format!("Reading {} failed", path.display()))?
+            }
+            false => String::new(),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lines: contents.lines().map(Line::parse).collect(),
+        })
+    }
+
+    /// Keys (without marker) known for the host.
+    pub fn keys(&self, host: &str) -> Vec<Key> {
+        self.entries()
+            .filter(|entry| entry.marker.is_none() && entry.matches(host))
+            .map(Entry::key)
+            .collect()
+    }
+
+    /// Keys marked `@revoked`, they are refused for every host.
+    pub fn is_revoked(&self, key: &Key) -> bool {
+        self.entries()
+            .any(|entry| entry.marker == Some(Marker::Revoked) && &entry.key() == key)
+    }
+
+    /// Add `key` for the host, replacing its previous keys of the same type.
+    /// Hashed entries stay hashed. Returns whether anything changed.
+    pub fn replace(&mut self, host: &str, key: &Key) -> bool {
+        if self.keys(host).contains(key) {
+            return false;
+        }
+        let hashed = self
+            .entries()
+            .any(|entry| entry.marker.is_none() && entry.is_hashed() && entry.matches(host));
+        self.remove(host, Some(&key.key_type));
+        self.lines.push(Line::Entry(Entry {
+            marker: None,
+            hosts: match hashed {
+                true => hash_host(host),
+                false => host.to_string(),
+            },
+            key_type: key.key_type.clone(),
+            key: key.key.clone(),
+            comment: None,
+            raw: None,
+        }));
+        true
+    }
+
+    /// Remove the keys of the host (only the ones of `key_type` when given). Plain entries
+    /// listing other hosts keep them, marked entries are never touched.
+    pub fn remove(&mut self, host: &str, key_type: Option<&str>) -> bool {
+        let mut changed = false;
+        self.lines.retain_mut(|line| {
+            let Line::Entry(entry) = line else {
+                return true;
+            };
+            if entry.marker.is_some()
+                || key_type.is_some_and(|key_type| key_type != entry.key_type)
+                || !entry.matches(host)
+            {
+                return true;
+            }
+            if entry.is_hashed() {
+                changed = true;
+                return false;
+            }
+            // Wildcard patterns cover other hosts as well, only literal names are removed
+            let patterns: Vec<&str> = entry.hosts.split(',').collect();
+            let remaining: Vec<&str> = patterns
+                .iter()
+                .copied()
+                .filter(|pattern| !pattern.eq_ignore_ascii_case(host))
+                .collect();
+            if remaining.len() == patterns.len() {
+                return true;
+            }
+            changed = true;
+            if remaining.is_empty() {
+                return false;
+            }
+            entry.hosts = remaining.join(",");
+            entry.raw = None;
+            true
+        });
+        changed
+    }
+
+    /// Contents as they would be saved.
+    pub fn to_contents(&self) -> String {
+        self.lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Write the file back after a timestamped backup of the previous one.
+    pub fn save(&self) -> Result<Option<PathBuf>> {
+        let backup = match self.path.exists() {
+            true => {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let backup = PathBuf::from(format!("{}.{timestamp}.bak", self.path.display()));
+                fs::copy(&self.path, &backup)
+                    .context(format!("Backing up {} failed", self.path.display()))?;
+                Some(backup)
+            }
+            false => None,
+        };
+        let dir = self
+            .path
+            .parent()
+            .ok_or_else(|| anyhow!("Invalid known hosts path {}", self.path.display()))?;
+        fs::create_dir_all(dir).context(format!("Creating {} failed", dir.display()))?;
+        let file = tempfile::NamedTempFile::new_in(dir)?;
+        fs::write(file.path(), self.to_contents())?;
+        file.persist(&self.path)
+            .context(format!("Writing {} failed", self.path.display()))?;
+        Ok(backup)
+    }
+
+    fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry(entry) => Some(entry),
+            Line::Other(_) => None,
+        })
+    }
+}
+
+impl Line {
+    fn parse(line: &str) -> Self {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Self::Other(line.to_string());
+        }
+        let mut fields = trimmed.split_whitespace().peekable();
+        let marker = match fields.peek() {
+            Some(&"@cert-authority") => Some(Marker::CertAuthority),
+            Some(&"@revoked") => Some(Marker::Revoked),
+            Some(field) if field.starts_with('@') => return Self::Other(line.to_string()),
+            _ => None,
+        };
+        if marker.is_some() {
+            fields.next();
+        }
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(hosts), Some(key_type), Some(key)) => {
+                let comment = fields.collect::<Vec<_>>().join(" ");
+                Self::Entry(Entry {
+                    marker,
+                    hosts: hosts.to_string(),
+                    key_type: key_type.to_string(),
+                    key: key.to_string(),
+                    comment: (!comment.is_empty()).then_some(comment),
+                    raw: Some(line.to_string()),
+                })
+            }
+            _ => Self::Other(line.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Other(line)
+            | Line::Entry(Entry {
+                raw: Some(line), ..
+            }) => write!(f, "{line}"),
+            Line::Entry(entry) => {
+                match entry.marker {
+                    Some(Marker::CertAuthority) => write!(f, "@cert-authority ")?,
+                    Some(Marker::Revoked) => write!(f, "@revoked ")?,
+                    None => {}
+                }
+                write!(f, "{} {} {}", entry.hosts, entry.key_type, entry.key)?;
+                if let Some(comment) = &entry.comment {
+                    write!(f, " {comment}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Entry {
+    fn key(&self) -> Key {
+        Key {
+            key_type: self.key_type.clone(),
+            key: self.key.clone(),
+        }
+    }
+
+    fn is_hashed(&self) -> bool {
+        self.hosts.starts_with("|1|")
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self.hosts.strip_prefix("|1|") {
+            Some(hashed) => {
+                let Some((salt, hash)) = hashed.split_once('|') else {
+                    return false;
+                };
+                match (Base64::decode_vec(salt), Base64::decode_vec(hash)) {
+                    (Ok(salt), Ok(hash)) => {
+                        let mut mac = hmac_sha1(&salt);
+                        mac.update(host.as_bytes());
+                        mac.verify_slice(&hash).is_ok()
+                    }
+                    _ => false,
+                }
+            }
+            None => ssh_config::match_patterns(std::slice::from_ref(&self.hosts), host),
+        }
+    }
+}
+
+/// `|1|salt|hash` form OpenSSH writes with `HashKnownHosts yes`.
+fn hash_host(host: &str) -> String {
+    let mut salt = [0; 20];
+    OsRng.fill_bytes(&mut salt);
+    let mut mac = hmac_sha1(&salt);
+    mac.update(host.as_bytes());
+    format!(
+        "|1|{}|{}",
+        Base64::encode_string(&salt),
+        Base64::encode_string(&mac.finalize().into_bytes())
+    )
+}
+
+fn hmac_sha1(key: &[u8]) -> Hmac<Sha1> {
+    Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known_hosts(contents: &str) -> KnownHosts {
+        KnownHosts {
+            path: PathBuf::from("known_hosts"),
+            lines: contents.lines().map(Line::parse).collect(),
+        }
+    }
+
+    fn key(key: &str) -> Key {
+        Key::parse(&format!("ssh-ed25519 {key}")).unwrap()
+    }
+
+    #[test]
+    fn port_pattern_is_not_a_prefix_match() {
+        let known_hosts = known_hosts("[10.0.0.11]:22 ssh-ed25519 AAAA11\n");
+        assert!(known_hosts.keys("[10.0.0.1]:22").is_empty());
+        assert_eq!(known_hosts.keys("[10.0.0.11]:22"), vec![key("AAAA11")]);
+    }
+
+    #[test]
+    fn hashed_lookup() {
+        // HMAC-SHA1 of "10.0.0.1" keyed by the salt 0x01..0x14, as OpenSSH hashes hosts
+        let known_hosts = known_hosts(
+            "|1|AQIDBAUGBwgJCgsMDQ4PEBESExQ=|79/IA74g5semQt2Fv4v4ynBMIg8= ssh-ed25519 AAAAH\n",
+        );
+        assert_eq!(known_hosts.keys("10.0.0.1"), vec![key("AAAAH")]);
+        assert!(known_hosts.keys("10.0.0.2").is_empty());
+    }
+
+    #[test]
+    fn replace_keeps_hashed_hosts_hashed() {
+        let mut known_hosts = known_hosts(&format!("{} ssh-ed25519 OLD\n", hash_host("host")));
+        assert!(known_hosts.replace("host", &key("NEW")));
+        assert_eq!(known_hosts.keys("host"), vec![key("NEW")]);
+        let contents = known_hosts.to_contents();
+        assert!(contents.starts_with("|1|") && !contents.contains("OLD"));
+    }
+
+    #[test]
+    fn revoked_keys() {
+        let mut known_hosts = known_hosts("@revoked * ssh-ed25519 REVOKED\n");
+        assert!(known_hosts.is_revoked(&key("REVOKED")));
+        assert!(!known_hosts.is_revoked(&key("OTHER")));
+        assert!(known_hosts.keys("host").is_empty());
+        known_hosts.remove("host", None);
+        assert_eq!(
+            known_hosts.to_contents(),
+            "@revoked * ssh-ed25519 REVOKED\n"
+        );
+    }
+
+    #[test]
+    fn cert_authority_left_alone() {
+        let contents = "@cert-authority *.example.com  ssh-ed25519 CA  ca@example\n\
+                        a.example.com,b.example.com ssh-ed25519 OLD\n";
+        let mut known_hosts = known_hosts(contents);
+        assert!(known_hosts.replace("a.example.com", &key("NEW")));
+        assert_eq!(
+            known_hosts.to_contents(),
+            "@cert-authority *.example.com  ssh-ed25519 CA  ca@example\n\
+             b.example.com ssh-ed25519 OLD\n\
+             a.example.com ssh-ed25519 NEW\n"
+        );
+    }
+
+    #[test]
+    fn bare_host_for_port_22() {
+        assert_eq!(host_pattern("host", 22), "host");
+        assert_eq!(host_pattern("host", 2222), "[host]:2222");
+        assert_eq!(host_pattern("2001:db8::1", 2222), "[2001:db8::1]:2222");
+        let known_hosts = known_hosts("[host]:2222 ssh-ed25519 AAAA\n");
+        assert!(known_hosts.keys(&host_pattern("host", 22)).is_empty());
+        assert_eq!(
+            known_hosts.keys(&host_pattern("host", 2222)),
+            vec![key("AAAA")]
+        );
+    }
+}
@@ -1,12 +1,21 @@
 use anyhow::Result;
-use dialoguer::{Confirm, theme::ColorfulTheme};
 
+pub mod askpass;
 pub mod command;
 pub mod disk;
+pub mod extra_files;
+pub mod file;
 pub mod git;
+pub mod known_hosts;
+pub mod prompt;
+pub mod sops;
+pub mod ssh_config;
+pub mod yaml;
 
-pub fn ask_confirmation(question: &str) -> Result<bool> {
-    Ok(Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(question)
-        .interact()?)
+pub fn ask_confirmation(id: &str, question: &str) -> Result<bool> {
+    prompt::confirm(id, question, None)
+}
+
+pub fn ask_confirmation_or(id: &str, question: &str, default: bool) -> Result<bool> {
+    prompt::confirm(id, question, Some(default))
 }
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use dialoguer::{Confirm, Input, Password, Select, theme::ColorfulTheme};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+pub type Validator = dyn Fn(&str) -> Result<(), String>;
+
+static PROMPTER: OnceLock<Box<dyn Prompter>> = OnceLock::new();
+
+/// Source of every answer asked to the operator, each question is identified by a stable id.
+pub trait Prompter: Send + Sync {
+    fn is_interactive(&self) -> bool;
+    fn confirm(&self, id: &str, prompt: &str, default: Option<bool>) -> Result<bool>;
+    fn input(
+        &self,
+        id: &str,
+        prompt: &str,
+        default: Option<&str>,
+        validate: Option<&Validator>,
+    ) -> Result<String>;
+    fn select(&self, id: &str, prompt: &str, items: &[String]) -> Result<usize>;
+    fn password(&self, id: &str, prompt: &str) -> Result<String>;
+}
+
+pub fn set_prompter(prompter: Box<dyn Prompter>) -> Result<()> {
+    PROMPTER
+        .set(prompter)
+        .map_err(|_| anyhow!("Prompter has already been set"))
+}
+
+fn prompter() -> &'static dyn Prompter {
+    PROMPTER
+        .get_or_init(|| Box::new(Interactive::new(false)))
+        .as_ref()
+}
+
+pub fn is_interactive() -> bool {
+    prompter().is_interactive()
+}
+
+pub fn confirm(id: &str, prompt: &str, default: Option<bool>) -> Result<bool> {
+    prompter().confirm(id, prompt, default)
+}
+
+pub fn input(id: &str, prompt: &str, default: Option<&str>) -> Result<String> {
+    prompter().input(id, prompt, default, None)
+}
+
+pub fn input_validated(
+    id: &str,
+    prompt: &str,
+    default: Option<&str>,
+    validate: &Validator,
+) -> Result<String> {
+    prompter().input(id, prompt, default, Some(validate))
+}
+
+pub fn select(id: &str, prompt: &str, items: &[String]) -> Result<usize> {
+    prompter().select(id, prompt, items)
+}
+
+pub fn password(id: &str, prompt: &str) -> Result<String> {
+    prompter().password(id, prompt)
+}
+
+/// Ask questions on the terminal, without terminal the default answer is used or it fails fast.
+pub struct Interactive {
+    tty: bool,
+}
+
+impl Interactive {
+    pub fn new(non_interactive: bool) -> Self {
+        let tty = !non_interactive && io::stdin().is_terminal() && io::stderr().is_terminal();
+        if !tty {
+            warn!("❗ No terminal attached: default answers are used, missing ones abort");
+        }
+        Self { tty }
+    }
+
+    fn missing(id: &str, prompt: &str) -> anyhow::Error {
+        anyhow!(
+            "Missing value for {id} ({prompt}): no terminal attached, pass it as a flag, in the inventory or in an answers file"
+        )
+    }
+}
+
+impl Prompter for Interactive {
+    fn is_interactive(&self) -> bool {
+        self.tty
+    }
+
+    fn confirm(&self, id: &str, prompt: &str, default: Option<bool>) -> Result<bool> {
+        if !self.tty {
+            let default = default.ok_or_else(|| Self::missing(id, prompt))?;
+            info!("🔸 Use default answer of {id}: {default}");
+            return Ok(default);
+        }
+        let theme = ColorfulTheme::default();
+        let mut confirm = Confirm::with_theme(&theme).with_prompt(prompt);
+        if let Some(default) = default {
+            confirm = confirm.default(default);
+        }
+        Ok(confirm.interact()?)
+    }
+
+    fn input(
+        &self,
+        id: &str,
+        prompt: &str,
+        default: Option<&str>,
+        validate: Option<&Validator>,
+    ) -> Result<String> {
+        if !self.tty {
+            let default = default.ok_or_else(|| Self::missing(id, prompt))?;
+            info!("🔸 Use default answer of {id}: {default}");
+            return Ok(default.to_string());
+        }
+        let theme = ColorfulTheme::default();
+        let mut input = Input::<String>::with_theme(&theme)
+            .with_prompt(prompt)
+            .allow_empty(false);
+        if let Some(default) = default {
+            input = input.default(default.to_string()).show_default(true);
+        }
+        if let Some(validate) = validate {
+            input = input.validate_with(|input: &String| validate(input));
+        }
+        Ok(input.interact_text()?)
+    }
+
+    fn select(&self, id: &str, prompt: &str, items: &[String]) -> Result<usize> {
+        if !self.tty {
+            return Err(Self::missing(id, prompt));
+        }
+        Ok(Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .items(items)
+            .interact()?)
+    }
+
+    fn password(&self, id: &str, prompt: &str) -> Result<String> {
+        if !self.tty {
+            return Err(Self::missing(id, prompt));
+        }
+        Ok(Password::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .allow_empty_password(false)
+            .interact()?)
+    }
+}
+
+/// Answer questions from an answers file, unknown questions are forwarded to `fallback`.
+pub struct Replay {
+    answers: HashMap<String, Value>,
+    fallback: Box<dyn Prompter>,
+}
+
+impl Replay {
+    pub fn load(path: &Path, fallback: Box<dyn Prompter>) -> Result<Self> {
+        info!("📜 Replay answers from {}", path.display());
+        let answers = match read_answers(path)? {
+            Some(answers) => answers.into_iter().collect(),
+            None => bail!("Answers file {} not found", path.display()),
+        };
+        Ok(Self { answers, fallback })
+    }
+
+    fn get(&self, id: &str) -> Option<&Value> {
+        let answer = self.answers.get(id);
+        if answer.is_some() {
+            info!("🔸 Replay answer of {id}");
+        }
+        answer
+    }
+}
+
+impl Prompter for Replay {
+    fn is_interactive(&self) -> bool {
+        self.fallback.is_interactive()
+    }
+
+    fn confirm(&self, id: &str, prompt: &str, default: Option<bool>) -> Result<bool> {
+        match self.get(id) {
+            Some(Value::Bool(answer)) => Ok(*answer),
+            Some(Value::String(answer)) => match answer.to_lowercase().as_str() {
+                "y" | "yes" | "true" => Ok(true),
+                "n" | "no" | "false" => Ok(false),
+                _ => bail!("Invalid answer for {id}: expected a boolean"),
+            },
+            Some(_) => bail!("Invalid answer for {id}: expected a boolean"),
+            None => self.fallback.confirm(id, prompt, default),
+        }
+    }
+
+    fn input(
+        &self,
+        id: &str,
+        prompt: &str,
+        default: Option<&str>,
+        validate: Option<&Validator>,
+    ) -> Result<String> {
+        let answer = match self.get(id) {
+            Some(Value::String(answer)) => answer.to_string(),
+            Some(Value::Number(answer)) => answer.to_string(),
+            Some(_) => bail!("Invalid answer for {id}: expected a string"),
+            None => return self.fallback.input(id, prompt, default, validate),
+        };
+        if let Some(validate) = validate {
+            validate(&answer).map_err(|err| anyhow!("Invalid answer for {id}: {err}"))?;
+        }
+        Ok(answer)
+    }
+
+    fn select(&self, id: &str, prompt: &str, items: &[String]) -> Result<usize> {
+        match self.get(id) {
+            Some(Value::String(answer)) => items
+                .iter()
+                .position(|item| item == answer || item.starts_with(&format!("{answer} ")))
+                .ok_or_else(|| anyhow!("Invalid answer for {id}: {answer} is not an option")),
+            Some(Value::Number(answer)) => answer
+                .as_u64()
+                .map(|index| index as usize)
+                .filter(|index| *index < items.len())
+                .ok_or_else(|| anyhow!("Invalid answer for {id}: {answer} is out of range")),
+            Some(_) => bail!("Invalid answer for {id}: expected an option"),
+            None => self.fallback.select(id, prompt, items),
+        }
+    }
+
+    fn password(&self, id: &str, prompt: &str) -> Result<String> {
+        match self.get(id) {
+            Some(Value::String(answer)) => Ok(answer.to_string()),
+            Some(_) => bail!("Invalid answer for {id}: expected a string"),
+            None => self.fallback.password(id, prompt),
+        }
+    }
+}
+
+/// Forward questions to `inner` and write every answer into an answers file.
+pub struct Recorder {
+    path: PathBuf,
+    answers: Mutex<Map<String, Value>>,
+    inner: Box<dyn Prompter>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, inner: Box<dyn Prompter>) -> Self {
+        info!("⏺️ Record answers into {}", path.display());
+        Self {
+            answers: Mutex::new(Map::new()),
+            path,
+            inner,
+        }
+    }
+
+    fn record(&self, id: &str, answer: Value) -> Result<()> {
+        let mut answers = self
+            .answers
+            .lock()
+            .map_err(|_| anyhow!("Recorded answers lock is poisoned"))?;
+        answers.insert(id.to_string(), answer);
+        write_answers(&self.path, &answers)
+    }
+}
+
+impl Prompter for Recorder {
+    fn is_interactive(&self) -> bool {
+        self.inner.is_interactive()
+    }
+
+    fn confirm(&self, id: &str, prompt: &str, default: Option<bool>) -> Result<bool> {
+        let answer = self.inner.confirm(id, prompt, default)?;
+        self.record(id, Value::Bool(answer))?;
+        Ok(answer)
+    }
+
+    fn input(
+        &self,
+        id: &str,
+        prompt: &str,
+        default: Option<&str>,
+        validate: Option<&Validator>,
+    ) -> Result<String> {
+        let answer = self.inner.input(id, prompt, default, validate)?;
+        self.record(id, Value::String(answer.clone()))?;
+        Ok(answer)
+    }
+
+    fn select(&self, id: &str, prompt: &str, items: &[String]) -> Result<usize> {
+        let answer = self.inner.select(id, prompt, items)?;
+        let item = items
+            .get(answer)
+            .ok_or_else(|| anyhow!("Selected option of {id} not found"))?;
+        self.record(id, Value::String(item.to_string()))?;
+        Ok(answer)
+    }
+
+    fn password(&self, id: &str, prompt: &str) -> Result<String> {
+        warn!("❗ Password of {id} is not recorded");
+        self.inner.password(id, prompt)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+fn read_answers(path: &Path) -> Result<Option<Map<String, Value>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(path).context(format!("Reading {} failed", path.display()))?;
+    let answers = if is_json(path) {
+        serde_json::from_str(&contents).context(format!("Parsing {} failed", path.display()))?
+    } else {
+        serde_yaml::from_str(&contents).context(format!("Parsing {} failed", path.display()))?
+    };
+    Ok(Some(answers))
+}
+
+fn write_answers(path: &Path, answers: &Map<String, Value>) -> Result<()> {
+    let contents = if is_json(path) {
+        serde_json::to_string_pretty(answers)?
+    } else {
+        serde_yaml::to_string(answers)?
+    };
+    fs::write(path, contents).context(format!("Writing {} failed", path.display()))
+}
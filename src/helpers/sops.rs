@@ -0,0 +1,726 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result, anyhow, bail};
+use regex::Regex;
+use tracing::warn;
+
+use crate::helpers::yaml::{Document, Entry, Node, Value, split_comment};
+
+/// Structured editor of a `.sops.yaml` file built on the lossless YAML document.
+pub struct SopsConfig {
+    doc: Document,
+}
+
+pub struct CreationRule {
+    pub index: usize,
+    pub path_regex: Option<String>,
+}
+
+impl SopsConfig {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let doc = Document::parse(contents);
+        if !matches!(doc.root().value, Value::Mapping(_)) {
+            bail!("SOPS file root is not a mapping")
+        }
+        Ok(Self { doc })
+    }
+
+    fn keys(&self) -> Result<&Node> {
+        self.doc
+            .root()
+            .get("keys")
+            .ok_or_else(|| anyhow!("No keys found in SOPS file"))
+    }
+
+    /// Sequences holding the anchored keys, `keys` itself or its sequence-valued entries.
+    fn key_lists(&self) -> Result<Vec<(Option<String>, Node)>> {
+        let keys = self.keys()?;
+        Ok(match &keys.value {
+            Value::Sequence(_) => vec![(None, keys.clone())],
+            Value::Mapping(entries) => entries
+                .iter()
+                .filter(|entry| matches!(entry.node.value, Value::Sequence(_)))
+                .map(|entry| (Some(entry.key.clone()), entry.node.clone()))
+                .collect(),
+            _ => bail!("Keys of SOPS file are neither a sequence nor a mapping"),
+        })
+    }
+
+    pub fn get_key(&self, name: &str) -> Result<Option<String>> {
+        Ok(self
+            .key_lists()?
+            .iter()
+            .flat_map(|(_, list)| list.items())
+            .find(|item| item.node.anchor.as_deref() == Some(name))
+            .and_then(|item| item.node.as_str().map(str::to_string)))
+    }
+
+    /// Add or update the key anchored as `name`, returns whether the file changed.
+    pub fn set_key(&mut self, name: &str, key: &str) -> Result<bool> {
+        let lists = self.key_lists()?;
+        let existing = lists
+            .iter()
+            .flat_map(|(_, list)| list.items())
+            .find(|item| item.node.anchor.as_deref() == Some(name));
+
+        if let Some(item) = existing {
+            let old = item
+                .node
+                .as_str()
+                .ok_or_else(|| anyhow!("Key {name} of SOPS file is not a scalar"))?;
+            if old == key {
+                return Ok(false);
+            }
+            let line = self.doc.line(item.line);
+            let anchor = format!("&{name}");
+            let start = line
+                .find(&anchor)
+                .map(|index| index + anchor.len())
+                .ok_or_else(|| anyhow!("Anchor {name} not found in SOPS file"))?;
+            let offset = line[start..]
+                .find(old)
+                .ok_or_else(|| anyhow!("Key {name} not found in SOPS file"))?;
+            let text = format!(
+                "{}{}{}",
+                &line[..start + offset],
+                key,
+                &line[start + offset + old.len()..]
+            );
+            self.doc.replace_line(item.line, text);
+            return Ok(true);
+        }
+
+        let (_, list) = lists
+            .iter()
+            .find(|(list_name, list)| {
+                list_name.as_deref() == Some("hosts") && !list.items().is_empty()
+            })
+            .or_else(|| lists.iter().find(|(_, list)| !list.items().is_empty()))
+            .ok_or_else(|| anyhow!("No key list found in SOPS file"))?;
+        self.doc.append_item(list, &format!("&{name} {key}"));
+        Ok(true)
+    }
+
+    /// Remove the key anchored as `name`, returns whether the file changed.
+    pub fn remove_key(&mut self, name: &str) -> Result<bool> {
+        let lists = self.key_lists()?;
+        let Some(item) = lists
+            .iter()
+            .flat_map(|(_, list)| list.items())
+            .find(|item| item.node.anchor.as_deref() == Some(name))
+        else {
+            return Ok(false);
+        };
+        self.doc
+            .remove_lines(item.line, item.node.last_line.max(item.line));
+        Ok(true)
+    }
+
+    pub fn creation_rules(&self) -> Vec<CreationRule> {
+        self.doc
+            .root()
+            .get("creation_rules")
+            .map(|rules| {
+                rules
+                    .items()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, rule)| CreationRule {
+                        index,
+                        path_regex: rule
+                            .node
+                            .get("path_regex")
+                            .and_then(|path_regex| path_regex.as_str().map(str::to_string)),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// First creation rule matching `path`, the one sops picks for this file.
+    pub fn rule_for(&self, path: &str) -> Result<Option<CreationRule>> {
+        for rule in self.creation_rules() {
+            let Some(path_regex) = rule.path_regex.as_deref() else {
+                return Ok(Some(rule));
+            };
+            let regex = Regex::new(path_regex)
+                .context(format!("Invalid creation rule path_regex: {path_regex}"))?;
+            if regex.is_match(path) {
+                return Ok(Some(rule));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Age recipients of a creation rule, with aliases resolved.
+    pub fn rule_recipients(&self, rule: usize) -> BTreeSet<String> {
+        let mut recipients = BTreeSet::new();
+        for entry in self.age_entries(rule) {
+            self.collect_recipients(&entry.node, &mut recipients);
+        }
+        recipients
+    }
+
+    fn collect_recipients(&self, node: &Node, recipients: &mut BTreeSet<String>) {
+        match &node.value {
+            Value::Scalar(keys) => recipients.extend(
+                keys.split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string),
+            ),
+            Value::Alias(name) => {
+                if let Some(node) = self.doc.root().find_anchor(name) {
+                    self.collect_recipients(node, recipients);
+                }
+            }
+            Value::Flow(items) => {
+                for item in items {
+                    match item.strip_prefix('*') {
+                        Some(name) => {
+                            if let Some(node) = self.doc.root().find_anchor(name) {
+                                self.collect_recipients(node, recipients);
+                            }
+                        }
+                        None => {
+                            recipients.insert(item.trim_matches(['"', '\'']).to_string());
+                        }
+                    }
+                }
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    self.collect_recipients(&item.node, recipients);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Anchor name of the key `recipient`, if it is declared in `keys`.
+    pub fn key_name(&self, recipient: &str) -> Option<String> {
+        self.key_lists()
+            .ok()?
+            .iter()
+            .flat_map(|(_, list)| list.items())
+            .find(|item| item.node.as_str() == Some(recipient))
+            .and_then(|item| item.node.anchor.clone())
+    }
+
+    /// Every `age` entry of a creation rule, at rule level and in its key groups.
+    fn age_entries(&self, rule: usize) -> Vec<Entry> {
+        let Some(rule) = self
+            .doc
+            .root()
+            .get("creation_rules")
+            .and_then(|rules| rules.items().get(rule))
+        else {
+            return Vec::new();
+        };
+        let mut entries: Vec<Entry> = rule.node.get_entry("age").into_iter().cloned().collect();
+        if let Some(groups) = rule.node.get("key_groups") {
+            entries.extend(
+                groups
+                    .items()
+                    .iter()
+                    .filter_map(|group| group.node.get_entry("age"))
+                    .cloned(),
+            );
+        }
+        entries
+    }
+
+    /// Reference the key `name` in every age list of the creation rule `rule`.
+    pub fn add_recipient(&mut self, rule: usize, name: &str) -> Result<bool> {
+        let alias = format!("*{name}");
+        let mut changed = false;
+        for index in 0..self.age_entries(rule).len() {
+            let entry = self.age_entries(rule).swap_remove(index);
+            match &entry.node.value {
+                Value::Sequence(items) => {
+                    if items
+                        .iter()
+                        .any(|item| matches!(&item.node.value, Value::Alias(a) if a == name))
+                    {
+                        continue;
+                    }
+                    self.doc.append_item(&entry.node, &alias);
+                }
+                Value::Flow(items) => {
+                    if items.contains(&alias) {
+                        continue;
+                    }
+                    let mut items = items.clone();
+                    items.push(alias.clone());
+                    self.doc.set_flow_items(&entry, &items);
+                }
+                Value::Null => self
+                    .doc
+                    .set_flow_items(&entry, std::slice::from_ref(&alias)),
+                Value::Scalar(_) => {
+                    // Comma separated recipients can't hold aliases, the key itself is added
+                    let key = self
+                        .get_key(name)?
+                        .ok_or_else(|| anyhow!("Key {name} not found in SOPS file"))?;
+                    let mut recipients = BTreeSet::new();
+                    self.collect_recipients(&entry.node, &mut recipients);
+                    if recipients.contains(&key) {
+                        continue;
+                    }
+                    if recipients.is_empty() {
+                        bail!("Creation rule {rule} has an empty age entry")
+                    }
+                    self.append_scalar_recipient(&entry.node, &key);
+                }
+                Value::Alias(list) => {
+                    bail!(
+                        "Creation rule {rule} uses the *{list} key list, add *{name} to it by hand"
+                    )
+                }
+                _ => {
+                    bail!("Creation rule {rule} has an age entry that is not a list of recipients")
+                }
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Add `key` after the last recipient of a comma separated scalar, inline or block.
+    fn append_scalar_recipient(&mut self, node: &Node, key: &str) {
+        let line = self.doc.line(node.last_line);
+        let text = if node.first_line == node.last_line {
+            let (code, comment) = split_comment(line);
+            match code.strip_suffix(['"', '\'']) {
+                Some(value) => format!("{value},{key}{}{comment}", &code[value.len()..]),
+                None => format!("{code},{key}{comment}"),
+            }
+        } else {
+            let line = line.trim_end();
+            match line.ends_with(',') {
+                true => format!("{line}{key}"),
+                false => format!("{line},{key}"),
+            }
+        };
+        self.doc.replace_line(node.last_line, text);
+    }
+
+    /// Remove `key` from a comma separated scalar, inline or block.
+    fn remove_scalar_recipient(&mut self, node: &Node, key: &str) -> bool {
+        let block = node.first_line != node.last_line;
+        let lines = match block {
+            true => node.first_line + 1..=node.last_line,
+            false => node.first_line..=node.first_line,
+        };
+        for index in lines {
+            let Some(text) = remove_list_item(self.doc.line(index), key) else {
+                continue;
+            };
+            if !block || !text.trim().is_empty() {
+                self.doc.replace_line(index, text);
+                return true;
+            }
+            self.doc.remove_lines(index, index);
+            // The previous line no longer separates two recipients
+            let last = index - 1;
+            if index == node.last_line && last > node.first_line {
+                let line = self.doc.line(last).trim_end();
+                if let Some(line) = line.strip_suffix(',') {
+                    self.doc.replace_line(last, line.to_string());
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Drop every reference to the key `name` from the creation rules.
+    pub fn remove_recipient(&mut self, name: &str) -> Result<bool> {
+        let alias = format!("*{name}");
+        let key = self.get_key(name)?;
+        let mut changed = false;
+        for rule in self.creation_rules() {
+            for index in 0..self.age_entries(rule.index).len() {
+                let entry = self.age_entries(rule.index).swap_remove(index);
+                match &entry.node.value {
+                    Value::Sequence(items) => {
+                        if let Some(item) = items
+                            .iter()
+                            .find(|item| matches!(&item.node.value, Value::Alias(a) if a == name))
+                        {
+                            if items.len() == 1 {
+                                warn!("❗ Creation rule {} has no age recipient left", rule.index);
+                            }
+                            self.doc
+                                .remove_lines(item.line, item.node.last_line.max(item.line));
+                            changed = true;
+                        }
+                    }
+                    Value::Flow(items) if items.contains(&alias) => {
+                        let items: Vec<String> = items
+                            .iter()
+                            .filter(|item| **item != alias)
+                            .cloned()
+                            .collect();
+                        self.doc.set_flow_items(&entry, &items);
+                        changed = true;
+                    }
+                    Value::Scalar(_) => {
+                        if let Some(key) = &key {
+                            changed |= self.remove_scalar_recipient(&entry.node, key);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// `line` without the comma separated `item`, None when it isn't there.
+fn remove_list_item(line: &str, item: &str) -> Option<String> {
+    let is_boundary = |c: Option<char>| !c.is_some_and(|c| c.is_alphanumeric());
+    let index = line
+        .match_indices(item)
+        .map(|(index, _)| index)
+        .find(|index| {
+            is_boundary(line[..*index].chars().next_back())
+                && is_boundary(line[index + item.len()..].chars().next())
+        })?;
+    let (before, after) = (&line[..index], &line[index + item.len()..]);
+    Some(match after.trim_start().strip_prefix(',') {
+        Some(after) => format!("{before}{}", after.trim_start()),
+        None => format!(
+            "{}{after}",
+            before.trim_end().strip_suffix(',').unwrap_or(before)
+        ),
+    })
+}
+
+/// Whether `contents` is a file encrypted by sops, whatever its format.
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.contains("ENC[AES256_GCM,") && contents.contains("mac")
+}
+
+/// Age recipients the data key of a sops encrypted file is wrapped for.
+pub fn file_recipients(contents: &str) -> BTreeSet<String> {
+    let regex = Regex::new("age1[qpzry9x8gf2tvdw0s3jn54khce6mua7l]{58}").expect("valid regex");
+    regex
+        .find_iter(contents)
+        .map(|recipient| recipient.as_str().to_string())
+        .collect()
+}
+
+impl std::fmt::Display for SopsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipients(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn set_key_keeps_comments_and_anchors() {
+        let mut sops = SopsConfig::parse(
+            "# sops config\n\
+             keys:\n\
+             \x20 # operators\n\
+             \x20 - &admin age1admin # laptop\n\
+             \x20 - &octopus age1old\n\
+             creation_rules:\n\
+             \x20 - path_regex: hosts/octopus/.*\n\
+             \x20   key_groups:\n\
+             \x20     - age:\n\
+             \x20         - *admin # always\n\
+             \x20         - *octopus\n",
+        )
+        .unwrap();
+        assert_eq!(sops.get_key("octopus").unwrap().as_deref(), Some("age1old"));
+        assert!(sops.set_key("octopus", "age1new").unwrap());
+        assert!(!sops.set_key("octopus", "age1new").unwrap());
+        assert!(sops.set_key("squid", "age1squid").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "# sops config\n\
+             keys:\n\
+             \x20 # operators\n\
+             \x20 - &admin age1admin # laptop\n\
+             \x20 - &octopus age1new\n\
+             \x20 - &squid age1squid\n\
+             creation_rules:\n\
+             \x20 - path_regex: hosts/octopus/.*\n\
+             \x20   key_groups:\n\
+             \x20     - age:\n\
+             \x20         - *admin # always\n\
+             \x20         - *octopus\n"
+        );
+        assert_eq!(
+            sops.rule_recipients(0),
+            recipients(&["age1admin", "age1new"])
+        );
+        assert_eq!(sops.key_name("age1new").as_deref(), Some("octopus"));
+    }
+
+    #[test]
+    fn grouped_keys_get_the_host_in_hosts() {
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             \x20 users:\n\
+             \x20   - &admin age1admin\n\
+             \x20 hosts:\n\
+             \x20   - &squid age1squid\n\
+             creation_rules:\n\
+             \x20 - age: *admin\n",
+        )
+        .unwrap();
+        assert!(sops.set_key("octopus", "age1octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             \x20 users:\n\
+             \x20   - &admin age1admin\n\
+             \x20 hosts:\n\
+             \x20   - &squid age1squid\n\
+             \x20   - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - age: *admin\n"
+        );
+        assert_eq!(sops.get_key("admin").unwrap().as_deref(), Some("age1admin"));
+        assert_eq!(sops.rule_recipients(0), recipients(&["age1admin"]));
+    }
+
+    #[test]
+    fn several_creation_rules() {
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: hosts/squid/.*\n\
+             \x20   age:\n\
+             \x20     - *admin\n\
+             \x20 - path_regex: hosts/octopus/.*\n\
+             \x20   age:\n\
+             \x20     - *admin\n\
+             \x20 - age: age1shared,age1other\n",
+        )
+        .unwrap();
+        let rule = sops
+            .rule_for("hosts/octopus/secrets.yaml")
+            .unwrap()
+            .unwrap();
+        assert_eq!(rule.index, 1);
+        assert_eq!(sops.rule_for("other.yaml").unwrap().unwrap().index, 2);
+        assert!(sops.add_recipient(1, "octopus").unwrap());
+        assert!(!sops.add_recipient(1, "octopus").unwrap());
+        assert!(sops.add_recipient(2, "octopus").unwrap());
+        assert_eq!(sops.rule_recipients(0), recipients(&["age1admin"]));
+        assert_eq!(
+            sops.rule_recipients(1),
+            recipients(&["age1admin", "age1octopus"])
+        );
+        assert_eq!(
+            sops.rule_recipients(2),
+            recipients(&["age1shared", "age1other", "age1octopus"])
+        );
+        assert!(
+            sops.to_string()
+                .contains("hosts/octopus/.*\n    age:\n      - *admin\n      - *octopus\n")
+        );
+    }
+
+    #[test]
+    fn flow_lists() {
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   age: [*admin] # flow\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age:\n",
+        )
+        .unwrap();
+        assert!(sops.add_recipient(0, "octopus").unwrap());
+        assert!(sops.add_recipient(1, "octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   age: [*admin, *octopus] # flow\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age: [*octopus]\n"
+        );
+        assert_eq!(
+            sops.rule_recipients(0),
+            recipients(&["age1admin", "age1octopus"])
+        );
+        assert!(sops.remove_recipient("octopus").unwrap());
+        assert!(sops.to_string().contains("age: [*admin] # flow\n"));
+    }
+
+    #[test]
+    fn scalar_recipients() {
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   age: age1admin,age1other # inline\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age: \"age1admin\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            sops.rule_recipients(0),
+            recipients(&["age1admin", "age1other"])
+        );
+        assert!(sops.add_recipient(0, "octopus").unwrap());
+        assert!(!sops.add_recipient(0, "octopus").unwrap());
+        assert!(sops.add_recipient(1, "octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   age: age1admin,age1other,age1octopus # inline\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age: \"age1admin,age1octopus\"\n"
+        );
+        assert!(sops.remove_recipient("octopus").unwrap());
+        assert!(
+            sops.to_string()
+                .contains("age: age1admin,age1other # inline\n")
+        );
+        assert!(sops.to_string().contains("age: \"age1admin\"\n"));
+    }
+
+    #[test]
+    fn block_scalar_recipients() {
+        let contents = "keys:\n\
+                        \x20 - &admin age1admin\n\
+                        \x20 - &octopus age1octopus\n\
+                        creation_rules:\n\
+                        \x20 - path_regex: a/.*\n\
+                        \x20   age: >-\n\
+                        \x20     age1admin,\n\
+                        \x20     age1other\n\
+                        \x20 - path_regex: b/.*\n\
+                        \x20   age: |\n\
+                        \x20     age1admin\n";
+        let mut sops = SopsConfig::parse(contents).unwrap();
+        assert_eq!(
+            sops.rule_recipients(0),
+            recipients(&["age1admin", "age1other"])
+        );
+        assert_eq!(sops.rule_for("b/secrets.yaml").unwrap().unwrap().index, 1);
+        assert!(sops.add_recipient(0, "octopus").unwrap());
+        assert!(sops.add_recipient(1, "octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   age: >-\n\
+             \x20     age1admin,\n\
+             \x20     age1other,age1octopus\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age: |\n\
+             \x20     age1admin,age1octopus\n"
+        );
+        assert_eq!(
+            sops.rule_recipients(0),
+            recipients(&["age1admin", "age1other", "age1octopus"])
+        );
+        assert!(sops.remove_recipient("octopus").unwrap());
+        assert_eq!(sops.to_string(), contents);
+
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             \x20 - &octopus age1octopus\n\
+             creation_rules:\n\
+             \x20 - age: >-\n\
+             \x20     age1admin,\n\
+             \x20     age1octopus\n",
+        )
+        .unwrap();
+        assert!(sops.remove_recipient("octopus").unwrap());
+        assert!(sops.to_string().ends_with("age: >-\n      age1admin\n"));
+    }
+
+    #[test]
+    fn unindented_sequences() {
+        let mut sops = SopsConfig::parse(
+            "keys:\n\
+             - &admin age1admin\n\
+             creation_rules:\n\
+             - path_regex: .*\n\
+             \x20 age:\n\
+             \x20 - *admin\n",
+        )
+        .unwrap();
+        assert!(sops.set_key("octopus", "age1octopus").unwrap());
+        assert!(sops.add_recipient(0, "octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             - &admin age1admin\n\
+             - &octopus age1octopus\n\
+             creation_rules:\n\
+             - path_regex: .*\n\
+             \x20 age:\n\
+             \x20 - *admin\n\
+             \x20 - *octopus\n"
+        );
+    }
+
+    #[test]
+    fn remove_key_and_recipients() {
+        let contents = "keys:\n\
+                        \x20 - &admin age1admin\n\
+                        \x20 # the host\n\
+                        \x20 - &octopus age1octopus\n\
+                        creation_rules:\n\
+                        \x20 - path_regex: a/.*\n\
+                        \x20   key_groups:\n\
+                        \x20     - age:\n\
+                        \x20         - *admin\n\
+                        \x20         - *octopus\n\
+                        \x20 - path_regex: b/.*\n\
+                        \x20   age: [*octopus, *admin]\n";
+        let mut sops = SopsConfig::parse(contents).unwrap();
+        assert!(sops.remove_key("octopus").unwrap());
+        assert!(sops.remove_recipient("octopus").unwrap());
+        assert!(!sops.remove_key("octopus").unwrap());
+        assert!(!sops.remove_recipient("octopus").unwrap());
+        assert_eq!(
+            sops.to_string(),
+            "keys:\n\
+             \x20 - &admin age1admin\n\
+             \x20 # the host\n\
+             creation_rules:\n\
+             \x20 - path_regex: a/.*\n\
+             \x20   key_groups:\n\
+             \x20     - age:\n\
+             \x20         - *admin\n\
+             \x20 - path_regex: b/.*\n\
+             \x20   age: [*admin]\n"
+        );
+    }
+}
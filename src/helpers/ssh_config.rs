@@ -0,0 +1,430 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tracing::warn;
+
+/// Include depth OpenSSH allows before giving up.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Subset of an OpenSSH client config (`~/.ssh/config`) resolving host aliases.
+#[derive(Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+/// Options applying when every condition matches (nested by `Include` inside a block).
+#[derive(Clone, Default)]
+struct Block {
+    conditions: Vec<Condition>,
+    options: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+enum Condition {
+    /// `Host` patterns, matched against the alias given on the command line
+    Host(Vec<String>),
+    /// `Match` criteria, all of them must match
+    Match(Vec<Criterion>),
+}
+
+#[derive(Clone)]
+enum Criterion {
+    All,
+    Host(Vec<String>),
+    OriginalHost(Vec<String>),
+    Unsupported,
+}
+
+/// Options of a host, the first obtained value of each one wins.
+#[derive(Default, Debug, Clone)]
+pub struct HostConfig {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+}
+
+impl SshConfig {
+    /// Load `~/.ssh/config`, an empty config if there is none.
+    pub fn load() -> Result<Self> {
+        let mut config = Self::default();
+        let Some(path) = dirs2::home_dir().map(|home| home.join(".ssh/config")) else {
+            return Ok(config);
+        };
+        if path.exists() {
+            config.parse_file(&path, &[], 0)?;
+        }
+        Ok(config)
+    }
+
+    fn parse_file(&mut self, path: &Path, conditions: &[Condition], depth: usize) -> Result<()> {
+        let contents =
+            fs::read_to_string(path).context(format!("Reading {} failed", path.display()))?;
+        self.parse(&contents, conditions, depth)
+    }
+
+    fn parse(&mut self, contents: &str, conditions: &[Condition], depth: usize) -> Result<()> {
+        let mut block = Block {
+            conditions: conditions.to_vec(),
+            options: Vec::new(),
+        };
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, args) = split_keyword(line);
+            match keyword.as_str() {
+                "host" => {
+                    self.blocks.push(block);
+                    let mut block_conditions = conditions.to_vec();
+                    block_conditions.push(Condition::Host(args));
+                    block = Block {
+                        conditions: block_conditions,
+                        options: Vec::new(),
+                    };
+                }
+                "match" => {
+                    self.blocks.push(block);
+                    let mut block_conditions = conditions.to_vec();
+                    block_conditions.push(Condition::Match(parse_criteria(&args)));
+                    block = Block {
+                        conditions: block_conditions,
+                        options: Vec::new(),
+                    };
+                }
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        warn!("❗ Too many nested ssh config includes, {line} skipped");
+                        continue;
+                    }
+                    // Included options come after the ones already read in this block
+                    let include_conditions = block.conditions.clone();
+                    self.blocks.push(block);
+                    for pattern in &args {
+                        for path in include_paths(pattern)? {
+                            self.parse_file(&path, &include_conditions, depth + 1)?;
+                        }
+                    }
+                    block = Block {
+                        conditions: include_conditions,
+                        options: Vec::new(),
+                    };
+                }
+                _ => {
+                    if let Some(value) = args.first() {
+                        block.options.push((keyword, value.to_string()));
+                    }
+                }
+            }
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Options of the host reached as `alias`.
+    pub fn resolve(&self, alias: &str) -> HostConfig {
+        let mut config = HostConfig::default();
+        for block in &self.blocks {
+            let hostname = config.hostname.as_deref().unwrap_or(alias);
+            if !block
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(alias, hostname))
+            {
+                continue;
+            }
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" if config.hostname.is_none() => {
+                        config.hostname = Some(value.replace("%h", alias).replace("%%", "%"))
+                    }
+                    "port" if config.port.is_none() => match value.parse() {
+                        Ok(port) => config.port = Some(port),
+                        Err(_) => warn!("❗ Invalid Port {value} in ssh config"),
+                    },
+                    "user" if config.user.is_none() => config.user = Some(value.to_string()),
+                    "identityfile" => config.identity_files.push(value.to_string()),
+                    "proxyjump" if config.proxy_jump.is_none() => {
+                        config.proxy_jump = Some(value.to_string())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let hostname = config.hostname.clone().unwrap_or(alias.to_string());
+        let user = config.user.clone().unwrap_or_default();
+        let home = dirs2::home_dir()
+            .map(|home| home.display().to_string())
+            .unwrap_or_default();
+        config.identity_files = config
+            .identity_files
+            .iter()
+            .map(|path| {
+                let path = path
+                    .replace("%d", &home)
+                    .replace("%h", &hostname)
+                    .replace("%r", &user)
+                    .replace("%%", "%");
+                match path.strip_prefix("~/") {
+                    Some(rest) => format!("{home}/{rest}"),
+                    None => path,
+                }
+            })
+            .collect();
+        if config.proxy_jump.as_deref() == Some("none") {
+            config.proxy_jump = None;
+        }
+        config
+    }
+}
+
+impl Condition {
+    fn matches(&self, alias: &str, hostname: &str) -> bool {
+        match self {
+            Condition::Host(patterns) => match_patterns(patterns, alias),
+            Condition::Match(criteria) => criteria.iter().all(|criterion| match criterion {
+                Criterion::All => true,
+                Criterion::Host(patterns) => match_patterns(patterns, hostname),
+                Criterion::OriginalHost(patterns) => match_patterns(patterns, alias),
+                Criterion::Unsupported => false,
+            }),
+        }
+    }
+}
+
+/// Split a config line into its lowercase keyword and its (unquoted) arguments.
+fn split_keyword(line: &str) -> (String, Vec<String>) {
+    let (keyword, rest) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+        Some(index) => (&line[..index], &line[index..]),
+        None => (line, ""),
+    };
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    (keyword.to_lowercase(), args)
+}
+
+fn parse_criteria(args: &[String]) -> Vec<Criterion> {
+    let mut criteria = Vec::new();
+    let mut args = args.iter();
+    while let Some(criterion) = args.next() {
+        let patterns = |value: Option<&String>| {
+            value
+                .map(|value| value.split(',').map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+        criteria.push(match criterion.to_lowercase().as_str() {
+            "all" => Criterion::All,
+            "host" => Criterion::Host(patterns(args.next())),
+            "originalhost" => Criterion::OriginalHost(patterns(args.next())),
+            name => {
+                warn!("❗ Match {name} of ssh config isn't supported, its block is skipped");
+                if !matches!(name, "canonical" | "final") {
+                    args.next();
+                }
+                Criterion::Unsupported
+            }
+        });
+    }
+    criteria
+}
+
+/// A host matches when one of the patterns does and none of the `!` negated ones do.
+pub fn match_patterns(patterns: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    let mut matched = false;
+    for pattern in patterns.iter().flat_map(|pattern| pattern.split(',')) {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(&negated.to_lowercase(), &host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(&pattern.to_lowercase(), &host),
+        }
+    }
+    matched
+}
+
+/// Match `text` against a pattern with `*` and `?` wildcards.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Files of an `Include`, relative paths are under `~/.ssh` and file names may be globs.
+fn include_paths(pattern: &str) -> Result<Vec<PathBuf>> {
+    let ssh_dir = dirs2::home_dir().unwrap_or_default().join(".ssh");
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => dirs2::home_dir().unwrap_or_default().join(rest),
+        None if Path::new(pattern).is_absolute() => PathBuf::from(pattern),
+        None => ssh_dir.join(pattern),
+    };
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let name = name.to_string_lossy();
+    if !name.contains(['*', '?']) {
+        return Ok(if path.exists() {
+            vec![path]
+        } else {
+            Vec::new()
+        });
+    }
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("Reading {} failed", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| wildcard_match(&name, &entry.file_name().to_string_lossy()))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(contents: &str) -> SshConfig {
+        let mut config = SshConfig::default();
+        config.parse(contents, &[], 0).unwrap();
+        config
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn first_value_wins() {
+        let config = config(
+            "Host web\n\
+             \x20 User first\n\
+             \x20 IdentityFile ~/.ssh/web\n\
+             Host *\n\
+             \x20 User second\n\
+             \x20 Port=2222\n\
+             \x20 IdentityFile ~/.ssh/default\n",
+        );
+        let web = config.resolve("web");
+        assert_eq!(web.user.as_deref(), Some("first"));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(web.identity_files.len(), 2);
+        assert_eq!(config.resolve("other").user.as_deref(), Some("second"));
+    }
+
+    #[test]
+    fn host_and_match_blocks() {
+        let config = config(
+            "Host web\n\
+             \x20 HostName 10.0.0.5\n\
+             Match host 10.0.0.*\n\
+             \x20 User admin\n\
+             Match originalhost web\n\
+             \x20 Port 2222\n\
+             Match exec true\n\
+             \x20 ProxyJump bastion\n",
+        );
+        let web = config.resolve("web");
+        assert_eq!(web.hostname.as_deref(), Some("10.0.0.5"));
+        assert_eq!(web.user.as_deref(), Some("admin"));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(web.proxy_jump, None);
+        let direct = config.resolve("10.0.0.6");
+        assert_eq!(direct.user.as_deref(), Some("admin"));
+        assert_eq!(direct.port, None);
+    }
+
+    #[test]
+    fn negated_and_wildcard_patterns() {
+        assert!(match_patterns(&patterns(&["*.lan", "!db.lan"]), "web.lan"));
+        assert!(!match_patterns(&patterns(&["*.lan", "!db.lan"]), "db.lan"));
+        assert!(!match_patterns(&patterns(&["!db.lan"]), "web.lan"));
+        assert!(match_patterns(&patterns(&["web?,db"]), "WEB1"));
+        assert!(!match_patterns(&patterns(&["web?"]), "web10"));
+        assert!(match_patterns(&patterns(&["10.0.*.1"]), "10.0.42.1"));
+
+        let config = config("Host * !bastion\n  ProxyJump bastion\n");
+        assert_eq!(config.resolve("web").proxy_jump.as_deref(), Some("bastion"));
+        assert_eq!(config.resolve("bastion").proxy_jump, None);
+    }
+
+    #[test]
+    fn include_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.conf"), "Host web\n  User from-b\n").unwrap();
+        fs::write(dir.path().join("a.conf"), "Host web\n  User from-a\n").unwrap();
+        fs::write(dir.path().join("skipped"), "Host web\n  Port 2222\n").unwrap();
+        let config = config(&format!(
+            "Host web\n  Include {}/*.conf\n  HostName web.lan\n",
+            dir.path().display()
+        ));
+        let web = config.resolve("web");
+        assert_eq!(web.user.as_deref(), Some("from-a"));
+        assert_eq!(web.hostname.as_deref(), Some("web.lan"));
+        assert_eq!(web.port, None);
+    }
+
+    #[test]
+    fn tokens_are_expanded() {
+        let config = config(
+            "Host web\n\
+             \x20 HostName %h.lan\n\
+             \x20 User deploy\n\
+             \x20 IdentityFile %d/.ssh/%h-%r\n\
+             \x20 IdentityFile ~/.ssh/100%%\n",
+        );
+        let home = dirs2::home_dir().unwrap().display().to_string();
+        let web = config.resolve("web");
+        assert_eq!(web.hostname.as_deref(), Some("web.lan"));
+        assert_eq!(
+            web.identity_files,
+            [
+                format!("{home}/.ssh/web.lan-deploy"),
+                format!("{home}/.ssh/100%")
+            ]
+        );
+    }
+}
@@ -0,0 +1,594 @@
+use std::fmt;
+
+/// Lossless model of a block-style YAML document.
+///
+/// The source lines are kept verbatim and a node tree indexes them, so edits only touch the
+/// lines they change and comments, anchors and layout survive.
+pub struct Document {
+    lines: Vec<String>,
+    trailing_newline: bool,
+    root: Node,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub anchor: Option<String>,
+    pub value: Value,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Scalar(String),
+    Alias(String),
+    Flow(Vec<String>),
+    Mapping(Vec<Entry>),
+    Sequence(Vec<Item>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub line: usize,
+    pub node: Node,
+}
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub line: usize,
+    pub indent: usize,
+    pub node: Node,
+}
+
+#[derive(Debug)]
+struct Token {
+    line: usize,
+    indent: usize,
+    kind: TokenKind,
+}
+
+#[derive(Debug)]
+enum TokenKind {
+    Dash,
+    Text(String),
+    /// Content of a block scalar (`|`, `>`), on the lines following its header
+    Block(String),
+}
+
+impl Node {
+    fn new(value: Value, first_line: usize, last_line: usize) -> Self {
+        Self {
+            anchor: None,
+            value,
+            first_line,
+            last_line,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Mapping(entries) => entries
+                .iter()
+                .find(|entry| entry.key == key)
+                .map(|entry| &entry.node),
+            _ => None,
+        }
+    }
+
+    pub fn get_entry(&self, key: &str) -> Option<&Entry> {
+        match &self.value {
+            Value::Mapping(entries) => entries.iter().find(|entry| entry.key == key),
+            _ => None,
+        }
+    }
+
+    pub fn items(&self) -> &[Item] {
+        match &self.value {
+            Value::Sequence(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Find the node anchored as `name` in this subtree.
+    pub fn find_anchor(&self, name: &str) -> Option<&Node> {
+        if self.anchor.as_deref() == Some(name) {
+            return Some(self);
+        }
+        match &self.value {
+            Value::Mapping(entries) => entries
+                .iter()
+                .find_map(|entry| entry.node.find_anchor(name)),
+            Value::Sequence(items) => items.iter().find_map(|item| item.node.find_anchor(name)),
+            _ => None,
+        }
+    }
+}
+
+impl Document {
+    pub fn parse(contents: &str) -> Self {
+        let lines: Vec<String> = contents.lines().map(str::to_string).collect();
+        let root = parse_lines(&lines);
+        Self {
+            lines,
+            trailing_newline: contents.ends_with('\n'),
+            root,
+        }
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn line(&self, index: usize) -> &str {
+        &self.lines[index]
+    }
+
+    pub fn replace_line(&mut self, index: usize, text: String) {
+        self.lines[index] = text;
+        self.reparse();
+    }
+
+    pub fn insert_line(&mut self, index: usize, text: String) {
+        self.lines.insert(index.min(self.lines.len()), text);
+        self.reparse();
+    }
+
+    pub fn remove_lines(&mut self, first: usize, last: usize) {
+        self.lines.drain(first..=last);
+        self.reparse();
+    }
+
+    /// Append `text` as a new item of `sequence`, aligned with its existing items.
+    pub fn append_item(&mut self, sequence: &Node, text: &str) {
+        let Some(last) = sequence.items().last() else {
+            return;
+        };
+        let line = format!("{}- {}", " ".repeat(last.indent), text);
+        self.insert_line(last.node.last_line.max(last.line) + 1, line);
+    }
+
+    /// Set the items of an empty (`key:`) or flow (`key: [..]`) sequence entry.
+    pub fn set_flow_items(&mut self, entry: &Entry, items: &[String]) {
+        let line = &self.lines[entry.line];
+        let text = match (line.find('['), line.rfind(']')) {
+            (Some(start), Some(end)) if start < end => {
+                format!("{}{}{}", &line[..=start], items.join(", "), &line[end..])
+            }
+            _ => {
+                let (code, comment) = split_comment(line);
+                format!("{} [{}]{}", code.trim_end(), items.join(", "), comment)
+            }
+        };
+        self.replace_line(entry.line, text);
+    }
+
+    fn reparse(&mut self) {
+        self.root = parse_lines(&self.lines);
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.lines.join("\n"))?;
+        if self.trailing_newline {
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a line into its code and its trailing comment (with the leading spaces).
+pub fn split_comment(line: &str) -> (&str, &str) {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => {
+                let code = line[..index].trim_end();
+                return (code, &line[code.len()..]);
+            }
+            None => {}
+        }
+        previous = c;
+    }
+    (line, "")
+}
+
+fn tokenize(lines: &[String]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut line_index = 0;
+    while line_index < lines.len() {
+        let line = &lines[line_index];
+        line_index += 1;
+        let (code, _) = split_comment(line);
+        let trimmed = code.trim_start();
+        if trimmed.is_empty() || trimmed == "---" || trimmed.starts_with("%") {
+            continue;
+        }
+        let line_indent = code.len() - trimmed.len();
+        let mut indent = line_indent;
+        let mut text = trimmed.trim_end();
+        while text == "-" || text.starts_with("- ") {
+            tokens.push(Token {
+                line: line_index - 1,
+                indent,
+                kind: TokenKind::Dash,
+            });
+            let rest = &text[1..];
+            let content = rest.trim_start();
+            indent += 1 + rest.len() - content.len();
+            text = content;
+        }
+        if text.is_empty() {
+            continue;
+        }
+        tokens.push(Token {
+            line: line_index - 1,
+            indent,
+            kind: TokenKind::Text(text.to_string()),
+        });
+
+        // The lines of a block scalar are more indented than its key, or its dash
+        let (parent, value) = match split_key(text) {
+            Some((_, value)) => (indent, value),
+            None => (line_indent, text),
+        };
+        let Some(folded) = block_header(split_anchor(value).1) else {
+            continue;
+        };
+        let mut content = Vec::new();
+        while let Some(line) = lines.get(line_index) {
+            let trimmed = line.trim_start();
+            if !trimmed.is_empty() && line.len() - trimmed.len() <= parent {
+                break;
+            }
+            content.push(line.as_str());
+            line_index += 1;
+        }
+        while content.last().is_some_and(|line| line.trim().is_empty()) {
+            content.pop();
+            line_index -= 1;
+        }
+        if content.is_empty() {
+            continue;
+        }
+        let content_indent = content
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or_default();
+        let mut value = String::new();
+        for (index, line) in content.iter().enumerate() {
+            let line = line.get(content_indent..).unwrap_or_default().trim_end();
+            // Folded lines are joined by a space, an empty line keeps its line break
+            match index {
+                0 => {}
+                _ if !folded || line.is_empty() => value.push('\n'),
+                _ if !value.ends_with('\n') => value.push(' '),
+                _ => {}
+            }
+            value.push_str(line);
+        }
+        tokens.push(Token {
+            line: line_index - 1,
+            indent: content_indent,
+            kind: TokenKind::Block(value),
+        });
+    }
+    tokens
+}
+
+/// Whether `text` is the header of a block scalar, folded (`>`) or literal (`|`).
+fn block_header(text: &str) -> Option<bool> {
+    let folded = match text.chars().next()? {
+        '>' => true,
+        '|' => false,
+        _ => return None,
+    };
+    text[1..]
+        .chars()
+        .all(|c| c == '-' || c == '+' || c.is_ascii_digit())
+        .then_some(folded)
+}
+
+fn parse_lines(lines: &[String]) -> Node {
+    let tokens = tokenize(lines);
+    let mut parser = Parser { tokens, pos: 0 };
+    parser.parse_node(0, 0)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn last_line(&self, default: usize) -> usize {
+        self.pos
+            .checked_sub(1)
+            .and_then(|pos| self.tokens.get(pos))
+            .map(|token| token.line)
+            .unwrap_or(default)
+            .max(default)
+    }
+
+    fn parse_node(&mut self, min_indent: usize, line: usize) -> Node {
+        let Some(token) = self.peek() else {
+            return Node::new(Value::Null, line, line);
+        };
+        if token.indent < min_indent {
+            return Node::new(Value::Null, line, line);
+        }
+        let indent = token.indent;
+        match &token.kind {
+            TokenKind::Dash => self.parse_sequence(indent),
+            TokenKind::Text(text) if split_key(text).is_some() => self.parse_mapping(indent),
+            TokenKind::Text(text) => {
+                let (text, first_line) = (text.clone(), token.line);
+                self.pos += 1;
+                self.parse_value(&text, indent, first_line)
+            }
+            TokenKind::Block(text) => {
+                let node = Node::new(Value::Scalar(text.clone()), token.line, token.line);
+                self.pos += 1;
+                node
+            }
+        }
+    }
+
+    /// Node of the `text` written after a key or a dash, with its block scalar lines if any.
+    fn parse_value(&mut self, text: &str, indent: usize, line: usize) -> Node {
+        let mut node = parse_inline(text, line);
+        if block_header(split_anchor(text).1).is_some() {
+            node.value = match self.peek() {
+                Some(Token {
+                    kind: TokenKind::Block(content),
+                    ..
+                }) => {
+                    let content = content.clone();
+                    self.pos += 1;
+                    Value::Scalar(content)
+                }
+                _ => Value::Scalar(String::new()),
+            };
+        }
+        self.skip_deeper(indent);
+        node.last_line = self.last_line(line);
+        node
+    }
+
+    fn parse_sequence(&mut self, indent: usize) -> Node {
+        let first_line = self.peek().map(|token| token.line).unwrap_or_default();
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            if token.indent != indent || !matches!(token.kind, TokenKind::Dash) {
+                break;
+            }
+            let line = token.line;
+            self.pos += 1;
+            let node = self.parse_node(indent + 1, line);
+            items.push(Item { line, indent, node });
+        }
+        let last_line = self.last_line(first_line);
+        Node::new(Value::Sequence(items), first_line, last_line)
+    }
+
+    fn parse_mapping(&mut self, indent: usize) -> Node {
+        let first_line = self.peek().map(|token| token.line).unwrap_or_default();
+        let mut entries = Vec::new();
+        while let Some(token) = self.peek() {
+            let TokenKind::Text(text) = &token.kind else {
+                break;
+            };
+            if token.indent != indent {
+                break;
+            }
+            let Some((key, rest)) = split_key(text) else {
+                break;
+            };
+            let (key, rest, line) = (key.to_string(), rest.to_string(), token.line);
+            self.pos += 1;
+
+            let (anchor, rest) = split_anchor(&rest);
+            let mut node = if rest.is_empty() {
+                match self.peek() {
+                    Some(next) if next.indent == indent && matches!(next.kind, TokenKind::Dash) => {
+                        self.parse_sequence(indent)
+                    }
+                    _ => self.parse_node(indent + 1, line),
+                }
+            } else {
+                self.parse_value(rest, indent, line)
+            };
+            if node.anchor.is_none() {
+                node.anchor = anchor;
+            }
+            if matches!(node.value, Value::Null) {
+                node.first_line = line;
+                node.last_line = line;
+            }
+            entries.push(Entry { key, line, node });
+        }
+        let last_line = self.last_line(first_line);
+        Node::new(Value::Mapping(entries), first_line, last_line)
+    }
+
+    fn skip_deeper(&mut self, indent: usize) {
+        while self.peek().is_some_and(|token| token.indent > indent) {
+            self.pos += 1;
+        }
+    }
+}
+
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    if text.starts_with(['[', '{', '*', '&', '|', '>']) {
+        return None;
+    }
+    let mut quote: Option<char> = None;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '\'' || c == '"') && index == 0 => quote = Some(c),
+            None if c == ':' => {
+                let rest = &text[index + 1..];
+                if rest.is_empty() || rest.starts_with(' ') {
+                    return Some((unquote(text[..index].trim()), rest.trim()));
+                }
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+fn split_anchor(text: &str) -> (Option<String>, &str) {
+    match text.strip_prefix('&') {
+        Some(rest) => {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (Some(rest[..end].to_string()), rest[end..].trim())
+        }
+        None => (None, text),
+    }
+}
+
+fn parse_inline(text: &str, line: usize) -> Node {
+    let (anchor, text) = split_anchor(text);
+    let value = if let Some(alias) = text.strip_prefix('*') {
+        Value::Alias(alias.trim().to_string())
+    } else if text.starts_with('[') && text.ends_with(']') {
+        Value::Flow(
+            text[1..text.len() - 1]
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+        )
+    } else if text.is_empty() || text == "~" || text == "null" {
+        Value::Null
+    } else {
+        Value::Scalar(unquote(text).to_string())
+    };
+    let mut node = Node::new(value, line, line);
+    node.anchor = anchor;
+    node
+}
+
+fn unquote(text: &str) -> &str {
+    let text = text.trim();
+    if text.len() >= 2
+        && ((text.starts_with('"') && text.ends_with('"'))
+            || (text.starts_with('\'') && text.ends_with('\'')))
+    {
+        &text[1..text.len() - 1]
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_are_split_outside_quotes() {
+        assert_eq!(split_comment("a: b # c"), ("a: b", " # c"));
+        assert_eq!(split_comment("a: 'b # c'"), ("a: 'b # c'", ""));
+        assert_eq!(split_comment("a: b#c"), ("a: b#c", ""));
+        assert_eq!(split_comment("# c"), ("", "# c"));
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let contents = "# header\n\
+                        keys:\n\
+                        \x20 - &admin age1admin   # spaced\n\
+                        \n\
+                        rules:\n\
+                        - age: [*admin, 'age1x'] # flow\n\
+                        \x20 path_regex: \"a#b\"\n";
+        assert_eq!(Document::parse(contents).to_string(), contents);
+        let contents = "keys:\n  - &admin age1admin";
+        assert_eq!(Document::parse(contents).to_string(), contents);
+    }
+
+    #[test]
+    fn anchors_aliases_and_flow_lists() {
+        let doc = Document::parse(
+            "keys:\n\
+             - &admin age1admin # laptop\n\
+             rules:\n\
+             - age: [*admin, age1other]\n\
+             \x20 pgp: *admin\n",
+        );
+        let root = doc.root();
+        assert_eq!(
+            root.find_anchor("admin").and_then(Node::as_str),
+            Some("age1admin")
+        );
+        let keys = root.get("keys").unwrap();
+        assert_eq!(keys.items().len(), 1);
+        assert_eq!(keys.items()[0].indent, 0);
+        let rule = &root.get("rules").unwrap().items()[0].node;
+        assert!(matches!(
+            &rule.get("age").unwrap().value,
+            Value::Flow(items) if items == &["*admin", "age1other"]
+        ));
+        assert!(matches!(&rule.get("pgp").unwrap().value, Value::Alias(name) if name == "admin"));
+    }
+
+    #[test]
+    fn block_scalars() {
+        let doc = Document::parse(
+            "folded: >-\n\
+             \x20 a,\n\
+             \n\
+             \x20 b # not a comment\n\
+             literal: |\n\
+             \x20 key: not a mapping\n\
+             list:\n\
+             - >\n\
+             \x20 c\n\
+             after: d\n",
+        );
+        let root = doc.root();
+        let folded = root.get("folded").unwrap();
+        assert_eq!(folded.as_str(), Some("a,\nb # not a comment"));
+        assert_eq!((folded.first_line, folded.last_line), (0, 3));
+        assert_eq!(
+            root.get("literal").and_then(Node::as_str),
+            Some("key: not a mapping")
+        );
+        let list = root.get("list").unwrap();
+        assert_eq!(list.items()[0].node.as_str(), Some("c"));
+        assert_eq!(root.get("after").and_then(Node::as_str), Some("d"));
+    }
+
+    #[test]
+    fn append_item_aligns_with_the_last_item() {
+        let mut doc = Document::parse("list:\n    - a # first\n    - b\nafter: c\n");
+        let list = doc.root().get("list").unwrap().clone();
+        doc.append_item(&list, "c");
+        assert_eq!(
+            doc.to_string(),
+            "list:\n    - a # first\n    - b\n    - c\nafter: c\n"
+        );
+    }
+}
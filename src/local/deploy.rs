@@ -1,18 +1,34 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use tracing::{info, warn};
 
-use crate::{helpers, remote};
+use crate::{
+    helpers::{self, extra_files::ExtraFiles, prompt},
+    local::inventory::DeployOptions,
+    remote::{self, Privilege},
+};
 
 impl super::Host {
-    pub fn deploy_nixos_anywhere(&self, remote: &remote::Host) -> Result<bool> {
-        if !helpers::ask_confirmation("Do you want to run nixos-anywhere?")? {
+    /// Ask whether to run nixos-anywhere, before anything is prepared for it.
+    pub fn confirm_nixos_anywhere(&self) -> Result<bool> {
+        if !helpers::ask_confirmation("nixos-anywhere", "Do you want to run nixos-anywhere?")? {
             warn!("❗ Skipping deployments via nixos-anywhere");
             return Ok(false);
         }
+        Ok(true)
+    }
 
+    pub fn deploy_nixos_anywhere(
+        &self,
+        remote: &remote::Host,
+        extra_files: Option<&ExtraFiles>,
+    ) -> Result<()> {
         info!("🚀 Deploying via nixos-anywhere");
         let repo = self.get_repo()?;
-        let command = format!(
+        let options = repo
+            .get_host_entry()
+            .map(|entry| entry.deploy.clone())
+            .unwrap_or_default();
+        let mut command = format!(
             "nix run github:nix-community/nixos-anywhere -- --ssh-port {} --flake {}#{} --target-host {}@{}",
             remote.port,
             repo.path.display(),
@@ -20,13 +36,34 @@ impl super::Host {
             remote.user,
             remote.destination,
         );
+        if let Some(identity_file) = &remote.args.identity_file {
+            command.push_str(&format!(" -i {identity_file}"));
+        }
+        if let Some(jump) = &remote.jump {
+            command.push_str(&format!(" --ssh-option ProxyJump={jump}"));
+        }
+        if let Some(extra_files) = extra_files {
+            command.push_str(&format!(" --extra-files {}", extra_files.path().display()));
+        }
+        for arg in &options.nixos_anywhere_args {
+            command.push_str(&format!(" {arg}"));
+        }
         tracing::info!("🔸 {command}");
+        if self.dry_run {
+            info!("🔸 [dry-run] nixos-anywhere would be run");
+            return Ok(());
+        }
 
+        let (_askpass, envs) = remote.askpass_envs()?;
         loop {
-            match helpers::command::run(&command) {
-                Ok(_) => return Ok(true),
+            match helpers::command::run(&command, &envs) {
+                Ok(_) => return Ok(()),
                 Err(err) => {
-                    if !helpers::ask_confirmation("Do you want to retry?")? {
+                    if !helpers::ask_confirmation_or(
+                        "nixos-anywhere-retry",
+                        "Do you want to retry?",
+                        false,
+                    )? {
                         return Err(err);
                     }
                 }
@@ -34,34 +71,92 @@ impl super::Host {
         }
     }
 
-    pub fn deploy_nixos_rebuild(&self, remote: &remote::Host) -> Result<bool> {
-        if !helpers::ask_confirmation("Do you want to run nixos-rebuild?")? {
+    pub fn deploy_nixos_rebuild(&self, remote: &mut remote::Host) -> Result<bool> {
+        if !helpers::ask_confirmation("nixos-rebuild", "Do you want to run nixos-rebuild?")? {
             warn!("❗ Skipping deployments via nixos-rebuild");
             return Ok(false);
         }
 
         info!("🚀 Deploying nix-config via nixos-rebuild");
         let repo = self.get_repo()?;
+        let options = repo
+            .get_host_entry()
+            .map(|entry| entry.deploy.clone())
+            .unwrap_or_default();
+        let privilege = remote.privilege()?;
+        let flags = Self::nixos_rebuild_flags(&options, &privilege)?;
+        if flags.contains("--ask-sudo-password") && !prompt::is_interactive() {
+            bail!(
+                "nixos-rebuild needs --ask-sudo-password but no terminal is attached, set deploy.ask_sudo_password = false for passwordless sudo"
+            )
+        }
         let command = format!(
-            "NIX_SSHOPTS=\"-p {}\" nixos-rebuild switch --flake {}#{} --build-host {}@{} --target-host {}@{} --use-substitutes --sudo --ask-sudo-password",
-            remote.port,
+            "NIX_SSHOPTS=\"{}\" nixos-rebuild switch --flake {}#{} --build-host {} --target-host {}@{} {}",
+            Self::nix_sshopts(remote),
             repo.path.display(),
             repo.host,
+            options
+                .build_host
+                .clone()
+                .unwrap_or_else(|| format!("{}@{}", remote.user, remote.destination)),
             remote.user,
             remote.destination,
-            remote.user,
-            remote.destination,
+            flags,
         );
         tracing::info!("🔸 {command}");
+        if self.dry_run {
+            info!("🔸 [dry-run] nixos-rebuild would be run");
+            return Ok(true);
+        }
+
+        let (_askpass, envs) = remote.askpass_envs()?;
         loop {
-            match helpers::command::run(&command) {
+            match helpers::command::run(&command, &envs) {
                 Ok(_) => return Ok(true),
                 Err(err) => {
-                    if !helpers::ask_confirmation("Do you want to retry?")? {
+                    if !helpers::ask_confirmation_or(
+                        "nixos-rebuild-retry",
+                        "Do you want to retry?",
+                        false,
+                    )? {
                         return Err(err);
                     }
                 }
             }
         }
     }
+
+    fn nix_sshopts(remote: &remote::Host) -> String {
+        let mut opts = format!("-p {}", remote.port);
+        if let Some(identity_file) = &remote.args.identity_file {
+            opts.push_str(&format!(" -i {identity_file}"));
+        }
+        if let Some(jump) = &remote.jump {
+            opts.push_str(&format!(" -J {jump}"));
+        }
+        opts
+    }
+
+    /// Flags of nixos-rebuild, root is reached with the privilege mode of the host.
+    fn nixos_rebuild_flags(options: &DeployOptions, privilege: &Privilege) -> Result<String> {
+        let mut flags = Vec::new();
+        if options.use_substitutes.unwrap_or(true) {
+            flags.push("--use-substitutes".to_string());
+        }
+        match privilege {
+            Privilege::None => {}
+            Privilege::Sudo => flags.push("--sudo".to_string()),
+            Privilege::SudoPassword => {
+                flags.push("--sudo".to_string());
+                if options.ask_sudo_password.unwrap_or(true) {
+                    flags.push("--ask-sudo-password".to_string());
+                }
+            }
+            Privilege::Doas => bail!(
+                "nixos-rebuild only elevates with sudo, deploy as root or with privilege = sudo instead of doas"
+            ),
+        }
+        flags.extend(options.nixos_rebuild_args.iter().cloned());
+        Ok(flags.join(" "))
+    }
 }
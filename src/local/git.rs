@@ -3,13 +3,15 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result, anyhow};
-use dialoguer::{Input, Select, theme::ColorfulTheme};
+use anyhow::{Context, Result, anyhow, bail};
 use git2::Repository;
 use tempfile::TempDir;
 use tracing::info;
 
-use crate::helpers;
+use crate::{
+    helpers::{self, prompt},
+    local::inventory::{HostEntry, Inventory},
+};
 
 pub struct Repo {
     pub git: Repository,
@@ -17,19 +19,27 @@ pub struct Repo {
     #[allow(dead_code)]
     tmp_dir: Option<TempDir>,
     pub host: String,
+    pub inventory: Inventory,
 }
 
 impl Repo {
-    pub fn clone_nix_config(use_iso: bool, use_path: bool) -> Result<Self> {
-        let (repo, tmp_dir) = match use_path {
+    pub fn clone_nix_config(
+        use_iso: bool,
+        use_path: bool,
+        path: Option<&Path>,
+        host: Option<&str>,
+    ) -> Result<Self> {
+        let (repo, tmp_dir) = match use_path || path.is_some() {
             true => {
                 info!("📂 Get nix-config git repository ");
-                let path = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Enter nix-config path:")
-                    .default(env::current_dir()?.display().to_string())
-                    .allow_empty(false)
-                    .show_default(true)
-                    .interact_text()?;
+                let path = match path {
+                    Some(path) => path.display().to_string(),
+                    None => prompt::input(
+                        "flake",
+                        "Enter nix-config path:",
+                        Some(&env::current_dir()?.display().to_string()),
+                    )?,
+                };
                 let repo = helpers::git::get_repository_by_path(&path)?;
                 (repo, None)
             }
@@ -43,16 +53,18 @@ impl Repo {
         let repo_path = repo_dir
             .parent()
             .context("Could not get parent path of cloned git repository")?;
-        let host = Self::get_config_host(repo_path, use_iso)?;
+        let host = Self::get_config_host(repo_path, use_iso, host)?;
+        let inventory = Inventory::load(repo_path)?;
         Ok(Self {
             git: repo,
             path: repo_path.to_path_buf(),
             tmp_dir,
             host,
+            inventory,
         })
     }
 
-    fn get_config_host(repo_path: &Path, use_iso: bool) -> Result<String> {
+    fn get_config_host(repo_path: &Path, use_iso: bool, host: Option<&str>) -> Result<String> {
         let mut hosts =
             serde_json::from_str::<Vec<String>>(&helpers::command::run_with_stdout(&format!(
                 " nix eval --json {}#nixosConfigurations --apply builtins.attrNames",
@@ -64,16 +76,27 @@ impl Repo {
                 .filter(|host| host.starts_with("plankton"))
                 .collect::<Vec<String>>();
         }
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select a config host?")
-            .items(&hosts)
-            .interact()?;
+        if let Some(host) = host {
+            if !hosts.iter().any(|config_host| config_host == host) {
+                bail!("Config host {host} not found in nixosConfigurations")
+            }
+            return Ok(host.to_string());
+        }
+        let selection = prompt::select(
+            if use_iso { "installer-host" } else { "host" },
+            "Select a config host?",
+            &hosts,
+        )?;
         let host = hosts
             .get(selection)
             .ok_or_else(|| anyhow!("Selected host doesn't be found"))?;
         Ok(host.to_string())
     }
 
+    pub fn get_host_entry(&self) -> Option<&HostEntry> {
+        self.inventory.get_host(&self.host)
+    }
+
     pub fn get_host(&self) -> &str {
         if self.host.starts_with("plankton") {
             "plankton"
@@ -82,11 +105,19 @@ impl Repo {
         }
     }
 
-    pub fn config_changes(&self) -> Result<()> {
+    pub fn config_changes(&self, dry_run: bool) -> Result<()> {
+        if dry_run {
+            info!("🔸 [dry-run] No config changes written");
+            return Ok(());
+        }
         info!("📝 Untrack config changes");
         let files = helpers::git::untrack_changes(&self.git)?;
         files.iter().for_each(|file| println!("🔸 {file}"));
-        if helpers::ask_confirmation("Do you want to see the detail of those changes?")? {
+        if helpers::ask_confirmation_or(
+            "config-changes",
+            "Do you want to see the detail of those changes?",
+            false,
+        )? {
             for file in files {
                 info!(
                     "🔸 {}:\n{}",
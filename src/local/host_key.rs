@@ -0,0 +1,199 @@
+use std::{collections::BTreeMap, fs, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::{Algorithm, LineEnding, PrivateKey, PublicKey, rand_core::OsRng};
+use tempfile::Builder;
+use tracing::{info, warn};
+
+use crate::helpers::{self, extra_files::ExtraFiles, sops::SopsConfig};
+
+/// Sops encrypted backup of the ssh host keys, next to the host secrets.
+const HOST_KEYS_FILE: &str = "ssh_host_keys.yaml";
+
+impl super::Host {
+    /// Generate the ed25519 host key of the config host and add its age key into SOPS, so
+    /// secrets decrypt on the first boot. The key is backed up first, a failed install
+    /// leaves the secrets readable and the next deploy reuses it. Returns the OpenSSH public
+    /// key.
+    pub fn pregenerate_host_key(&self, extra_files: &ExtraFiles) -> Result<String> {
+        let repo = self.get_repo()?;
+        if self.has_host_keys_backup()? {
+            info!("⏭️ ssh host key already generated");
+            if let Some(public_key) = self.restore_host_keys(extra_files)? {
+                repo.config_changes(self.dry_run)?;
+                return Ok(public_key);
+            }
+        }
+        info!("🔑 Pre-generate ssh host key");
+        let mut key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+            .context("Host key (ssh) generation failed")?;
+        let public_key = key.public_key().to_openssh()?;
+        key.set_comment(format!("root@{}", repo.host));
+        let age_key = ssh_to_age::convert::ssh_public_key_to_age(&public_key)?;
+        info!("🔸 Age key of {}: {age_key}", repo.host);
+
+        extra_files.add(
+            "/etc/ssh/ssh_host_ed25519_key",
+            key.to_openssh(LineEnding::LF)?.as_bytes(),
+            0o600,
+        )?;
+        extra_files.add(
+            "/etc/ssh/ssh_host_ed25519_key.pub",
+            format!("{}\n", key.public_key().to_openssh()?).as_bytes(),
+            0o644,
+        )?;
+
+        self.backup_host_keys(&BTreeMap::from([
+            (
+                "ssh_host_ed25519_key".to_string(),
+                key.to_openssh(LineEnding::LF)?.to_string(),
+            ),
+            (
+                "ssh_host_ed25519_key.pub".to_string(),
+                format!("{}\n", key.public_key().to_openssh()?),
+            ),
+        ]))?;
+        let sops_config = self.update_sops(&age_key)?;
+        self.update_encrypt_file_keys(sops_config.as_ref())?;
+        repo.config_changes(self.dry_run)?;
+        Ok(public_key)
+    }
+
+    fn host_keys_path(&self) -> Result<PathBuf> {
+        let repo = self.get_repo()?;
+        Ok(PathBuf::from(format!(
+            "hosts/{}/{HOST_KEYS_FILE}",
+            repo.get_host()
+        )))
+    }
+
+    pub fn has_host_keys_backup(&self) -> Result<bool> {
+        Ok(self.get_repo()?.path.join(self.host_keys_path()?).exists())
+    }
+
+    /// Store the ssh host keys (file name, contents) sops encrypted into the flake.
+    pub fn backup_host_keys(&self, keys: &BTreeMap<String, String>) -> Result<()> {
+        let repo = self.get_repo()?;
+        let path = self.host_keys_path()?;
+        info!("💾 Back up ssh host keys into {}", path.display());
+        let sops_config = SopsConfig::parse(
+            &fs::read_to_string(repo.path.join(".sops.yaml"))
+                .context("Opening .sops.yaml failed")?,
+        )?;
+        // Without a rule of its own, the backup is readable by whoever reads the host secrets
+        let secrets_path = format!("hosts/{}/secrets.yaml", repo.get_host());
+        let rule = match sops_config.rule_for(&path.to_string_lossy())? {
+            Some(rule) => rule,
+            None => sops_config.rule_for(&secrets_path)?.ok_or_else(|| {
+                anyhow!(
+                    "No SOPS creation rule matches {} nor {secrets_path}",
+                    path.display()
+                )
+            })?,
+        };
+        let recipients = sops_config.rule_recipients(rule.index);
+        if recipients.is_empty() {
+            bail!("No age recipient to encrypt {} for", path.display())
+        }
+        if self.dry_run {
+            for name in keys.keys() {
+                info!("🔸 [dry-run] {name} would be backed up");
+            }
+            return Ok(());
+        }
+
+        let mut plain = Builder::new()
+            .suffix(".yaml")
+            .tempfile()
+            .context("Failed to create temp file")?;
+        plain.write_all(serde_yaml::to_string(keys)?.as_bytes())?;
+        let recipients = recipients.into_iter().collect::<Vec<_>>().join(",");
+        let plain_path = plain.path().display().to_string();
+        let encrypted = helpers::command::run_program_with_stdout(
+            &repo.path,
+            "sops",
+            &[
+                "--encrypt",
+                "--age",
+                &recipients,
+                "--input-type",
+                "yaml",
+                "--output-type",
+                "yaml",
+                &plain_path,
+            ],
+            &[],
+        )
+        .context("Encrypting ssh host keys failed")?;
+        helpers::file::write(&repo.path.join(&path), encrypted.as_bytes(), self.dry_run)?;
+        for name in keys.keys() {
+            info!("🔸 {name} backed up");
+        }
+        Ok(())
+    }
+
+    /// Backed up ssh host keys (file name, contents), decrypted.
+    fn decrypt_host_keys(&self) -> Result<BTreeMap<String, String>> {
+        let repo = self.get_repo()?;
+        let file = self.host_keys_path()?.display().to_string();
+        let decrypted = helpers::command::run_program_with_stdout(
+            &repo.path,
+            "sops",
+            &["--decrypt", "--output-type", "json", &file],
+            &self.get_sops_envs()?,
+        )
+        .context(format!("Decrypting {file} failed"))?;
+        serde_json::from_str(&decrypted).context(format!("Parsing decrypted {file} failed"))
+    }
+
+    /// Whether the backed up ed25519 host key is `public_key`, None without a backup.
+    pub fn host_keys_backup_matches(&self, public_key: &str) -> Result<Option<bool>> {
+        if !self.has_host_keys_backup()? {
+            return Ok(None);
+        }
+        let keys = self.decrypt_host_keys()?;
+        let Some(backup) = keys.get("ssh_host_ed25519_key.pub") else {
+            return Ok(Some(false));
+        };
+        let backup = PublicKey::from_openssh(backup.trim())
+            .context("Parsing backed up ed25519 public key failed")?;
+        let public_key =
+            PublicKey::from_openssh(public_key).context("Parsing host public key failed")?;
+        Ok(Some(backup.key_data() == public_key.key_data()))
+    }
+
+    /// Decrypt the backed up ssh host keys into the extra files, and make sure SOPS knows the
+    /// age key of the restored ed25519 key. Returns the restored ed25519 public key.
+    pub fn restore_host_keys(&self, extra_files: &ExtraFiles) -> Result<Option<String>> {
+        let path = self.host_keys_path()?;
+        if !self.has_host_keys_backup()? {
+            warn!("❗ No ssh host keys backup at {}", path.display());
+            return Ok(None);
+        }
+        info!("♻️ Restore ssh host keys from {}", path.display());
+        let file = path.display().to_string();
+        let keys = self.decrypt_host_keys()?;
+
+        for (name, contents) in &keys {
+            if name.contains('/') || !name.starts_with("ssh_host_") {
+                bail!("Unexpected ssh host key name in {file}: {name}")
+            }
+            let mode = if name.ends_with(".pub") { 0o644 } else { 0o600 };
+            extra_files.add(&format!("/etc/ssh/{name}"), contents.as_bytes(), mode)?;
+            info!("🔸 {name} restored");
+        }
+
+        let Some(public_key) = keys.get("ssh_host_ed25519_key.pub") else {
+            warn!("❗ No ed25519 host key in {file}, secrets won't decrypt on first boot");
+            return Ok(None);
+        };
+        let public_key = PublicKey::from_openssh(public_key.trim())
+            .context("Parsing restored ed25519 public key failed")?;
+        let public_key = PublicKey::from(public_key.key_data().clone()).to_openssh()?;
+        let age_key = ssh_to_age::convert::ssh_public_key_to_age(&public_key)?;
+        if let Some(sops_config) = self.update_sops(&age_key)? {
+            self.update_encrypt_file_keys(Some(&sops_config))?;
+        }
+        Ok(Some(public_key))
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    cli::RemoteArgs,
+    remote::{AuthMethod, Privilege},
+};
+
+pub const INVENTORY_FILE: &str = ".nix-bootstrap.toml";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    #[serde(default)]
+    pub hosts: HashMap<String, HostEntry>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HostEntry {
+    pub destination: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub auth: Option<AuthMethod>,
+    pub identity_file: Option<String>,
+    pub privilege: Option<Privilege>,
+    pub expect_fingerprint: Option<String>,
+    pub jump: Option<String>,
+    pub connect_timeout: Option<u64>,
+    pub command_timeout: Option<u64>,
+    pub keepalive: Option<u32>,
+    pub install_key: Option<String>,
+    pub disk: Option<String>,
+    #[serde(default)]
+    pub deploy: DeployOptions,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DeployOptions {
+    pub build_host: Option<String>,
+    pub use_substitutes: Option<bool>,
+    pub ask_sudo_password: Option<bool>,
+    pub pregenerate_host_key: Option<bool>,
+    pub keep_host_keys: Option<bool>,
+    pub persist_path: Option<String>,
+    #[serde(default)]
+    pub nixos_anywhere_args: Vec<String>,
+    #[serde(default)]
+    pub nixos_rebuild_args: Vec<String>,
+}
+
+impl Inventory {
+    pub fn load(repo_path: &Path) -> Result<Self> {
+        let path = repo_path.join(INVENTORY_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        info!("📒 Load host inventory from {}", path.display());
+        let contents =
+            fs::read_to_string(&path).context(format!("Reading {} failed", path.display()))?;
+        toml::from_str(&contents).context(format!("Parsing {} failed", path.display()))
+    }
+
+    pub fn get_host(&self, host: &str) -> Option<&HostEntry> {
+        self.hosts.get(host)
+    }
+}
+
+impl HostEntry {
+    pub fn get_remote_args(&self) -> RemoteArgs {
+        RemoteArgs {
+            destination: self.destination.clone(),
+            port: self.port,
+            user: self.user.clone(),
+            auth: self.auth.clone(),
+            identity_file: self.identity_file.clone(),
+            privilege: self.privilege.clone(),
+            expect_fingerprint: self.expect_fingerprint.clone(),
+            jump: self.jump.clone(),
+            connect_timeout: self.connect_timeout,
+            command_timeout: self.command_timeout,
+            keepalive: self.keepalive,
+            install_key: self.install_key.clone(),
+            disk: self.disk.clone(),
+        }
+    }
+}
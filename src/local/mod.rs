@@ -1,34 +1,66 @@
+use std::path::PathBuf;
+
 use anyhow::{Result, anyhow};
 
 use crate::local::{git::Repo, ssh::Info};
 
 mod deploy;
 mod git;
-mod ssh;
+mod host_key;
+mod inventory;
+mod secrets;
+pub mod ssh;
 mod update;
 
 pub struct Host {
     repo: Option<Repo>,
     pub ssh: Info,
+    pub flake_path: Option<PathBuf>,
+    sops_identity: Option<PathBuf>,
+    pub dry_run: bool,
 }
 
 impl Host {
-    pub fn new() -> Result<Self> {
-        let home_dir =
-            dirs2::home_dir().ok_or_else(|| anyhow!("Could not find local home directory"))?;
-        let ssh = ssh::Info::new(home_dir.join(".ssh/known_hosts"));
-        Ok(Self { repo: None, ssh })
+    pub fn new(
+        flake_path: Option<PathBuf>,
+        sops_identity: Option<PathBuf>,
+        known_hosts: Option<PathBuf>,
+        dry_run: bool,
+    ) -> Result<Self> {
+        let known_hosts = match known_hosts {
+            Some(known_hosts) => known_hosts,
+            None => dirs2::home_dir()
+                .ok_or_else(|| anyhow!("Could not find local home directory"))?
+                .join(".ssh/known_hosts"),
+        };
+        let ssh = ssh::Info::new(known_hosts, dry_run);
+        Ok(Self {
+            repo: None,
+            ssh,
+            flake_path,
+            sops_identity,
+            dry_run,
+        })
     }
 
-    pub fn set_nix_config(&mut self, use_iso: bool, use_path: bool) -> Result<()> {
-        self.repo = Some(Repo::clone_nix_config(use_iso, use_path)?);
+    pub fn set_nix_config(
+        &mut self,
+        use_iso: bool,
+        use_path: bool,
+        host: Option<&str>,
+    ) -> Result<()> {
+        self.repo = Some(Repo::clone_nix_config(
+            use_iso,
+            use_path,
+            self.flake_path.as_deref(),
+            host,
+        )?);
         Ok(())
     }
 
     pub fn get_repo(&self) -> Result<&Repo> {
-        Ok(self
-            .repo
+        self.repo
             .as_ref()
-            .ok_or_else(|| anyhow!("Git repo not seems to be cloned"))?)
+            .ok_or_else(|| anyhow!("Git repo not seems to be cloned"))
     }
 }
@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeSet,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::{LineEnding, PrivateKey};
+use tracing::{info, warn};
+
+use crate::helpers::{
+    self, prompt,
+    sops::{self, SopsConfig},
+};
+
+/// Operator age identity used by sops to unwrap the data keys.
+enum Identity {
+    /// Age key file (`AGE-SECRET-KEY-...` lines)
+    AgeKeyFile(PathBuf),
+    /// OpenSSH ed25519 private key converted into an age identity
+    SshKey(PathBuf),
+    /// Identity already given to sops through its environment
+    Env(&'static str),
+}
+
+impl Identity {
+    fn from_path(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).context(format!("Reading {} failed", path.display()))?;
+        if contents.contains("AGE-SECRET-KEY-") {
+            Ok(Self::AgeKeyFile(path.to_path_buf()))
+        } else if contents.contains("OPENSSH PRIVATE KEY") {
+            Ok(Self::SshKey(path.to_path_buf()))
+        } else {
+            bail!(
+                "{} is neither an age key file nor an OpenSSH private key",
+                path.display()
+            )
+        }
+    }
+
+    /// Environment handing the identity over to sops.
+    fn envs(&self) -> Result<Vec<(String, String)>> {
+        match self {
+            Self::AgeKeyFile(path) => Ok(vec![(
+                "SOPS_AGE_KEY_FILE".to_string(),
+                path.display().to_string(),
+            )]),
+            Self::SshKey(path) => {
+                let mut key = PrivateKey::read_openssh_file(path)
+                    .context(format!("Reading {} failed", path.display()))?;
+                if key.is_encrypted() {
+                    let passphrase = prompt::password(
+                        "sops-identity-passphrase",
+                        &format!("Enter passphrase of {}:", path.display()),
+                    )?;
+                    key = key
+                        .decrypt(passphrase)
+                        .map_err(|err| anyhow!("Decrypting {} failed: {err}", path.display()))?;
+                }
+                let age_key = ssh_to_age::convert::ssh_private_key_to_age(
+                    key.to_openssh(LineEnding::LF)?.as_bytes(),
+                )
+                .context(format!(
+                    "Converting {} to an age identity failed (only ed25519 keys are supported)",
+                    path.display()
+                ))?;
+                Ok(vec![("SOPS_AGE_KEY".to_string(), age_key.secret)])
+            }
+            Self::Env(_) => Ok(Vec::new()),
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AgeKeyFile(path) => write!(f, "age key file {}", path.display()),
+            Self::SshKey(path) => write!(f, "ssh key {}", path.display()),
+            Self::Env(var) => write!(f, "${var}"),
+        }
+    }
+}
+
+impl super::Host {
+    /// `--sops-identity`, then the sops environment and default key file, then `~/.ssh/id_ed25519`.
+    fn get_sops_identity(&self) -> Result<Identity> {
+        if let Some(path) = &self.sops_identity {
+            return Identity::from_path(path);
+        }
+        for var in ["SOPS_AGE_KEY", "SOPS_AGE_KEY_FILE"] {
+            if env::var_os(var).is_some() {
+                return Ok(Identity::Env(var));
+            }
+        }
+        if let Some(path) = dirs2::config_dir().map(|dir| dir.join("sops/age/keys.txt"))
+            && path.exists()
+        {
+            return Ok(Identity::AgeKeyFile(path));
+        }
+        if let Some(path) = dirs2::home_dir().map(|dir| dir.join(".ssh/id_ed25519"))
+            && path.exists()
+        {
+            return Ok(Identity::SshKey(path));
+        }
+        bail!("No sops identity found, use --sops-identity <age key file | ssh private key>")
+    }
+
+    /// Environment giving the operator identity to a sops decryption.
+    pub(super) fn get_sops_envs(&self) -> Result<Vec<(String, String)>> {
+        let identity = self.get_sops_identity()?;
+        info!("🔸 Decrypt data keys with {identity}");
+        identity.envs()
+    }
+
+    /// Encrypted files of the repo with the creation rule sops applies to them.
+    fn get_encrypted_files(repo_path: &Path, sops: &SopsConfig) -> Result<Vec<(PathBuf, usize)>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![repo_path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).context(format!("Reading {} failed", dir.display()))? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_symlink() || entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let path = entry.path();
+                if file_type.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let relative = path.strip_prefix(repo_path)?.to_path_buf();
+                let Some(rule) = sops.rule_for(&relative.to_string_lossy())? else {
+                    continue;
+                };
+                let Ok(contents) = fs::read_to_string(&path) else {
+                    continue;
+                };
+                if sops::is_encrypted(&contents) {
+                    files.push((relative, rule.index));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Rekey the encrypted files for the recipients of `sops_config`, the `.sops.yaml` just
+    /// edited (not written under `--dry-run`), else the one on disk.
+    pub fn update_encrypt_file_keys(&self, sops_config: Option<&SopsConfig>) -> Result<()> {
+        info!("🔁 Update encrypted files keys");
+        let repo = self.get_repo()?;
+        let sops_path = repo.path.join(".sops.yaml");
+        let read_config;
+        let sops_config = match sops_config {
+            Some(sops_config) => sops_config,
+            None => {
+                read_config = SopsConfig::parse(
+                    &fs::read_to_string(&sops_path)
+                        .context(format!("Opening {} failed", sops_path.display()))?,
+                )?;
+                &read_config
+            }
+        };
+        let files = Self::get_encrypted_files(&repo.path, sops_config)?;
+        if files.is_empty() {
+            warn!("❗ No encrypted file matched by the SOPS creation rules");
+            return Ok(());
+        }
+
+        let identity = self.get_sops_identity()?;
+        info!("🔸 Decrypt data keys with {identity}");
+        let mut envs = None;
+        let mut failed = Vec::new();
+        for (path, rule) in files {
+            let file_path = repo.path.join(&path);
+            let before = sops::file_recipients(&fs::read_to_string(&file_path)?);
+            let expected = sops_config.rule_recipients(rule);
+            if before == expected {
+                info!("✅ {} already up to date", path.display());
+                continue;
+            }
+            if self.dry_run {
+                Self::report_recipients(sops_config, &path, &before, &expected, true);
+                continue;
+            }
+
+            let envs = match &envs {
+                Some(envs) => envs,
+                None => envs.insert(identity.envs()?),
+            };
+            let config = sops_path.display().to_string();
+            let file = path.display().to_string();
+            info!("🔸 sops updatekeys -y {file}");
+            match helpers::command::run_program(
+                &repo.path,
+                "sops",
+                &["--config", &config, "updatekeys", "-y", &file],
+                envs,
+            ) {
+                Ok(()) => {
+                    let after = sops::file_recipients(&fs::read_to_string(&file_path)?);
+                    Self::report_recipients(sops_config, &path, &before, &after, false);
+                }
+                Err(err) => {
+                    warn!("❗ Rekeying {file} failed: {err:#}");
+                    failed.push(file);
+                }
+            }
+        }
+        if !failed.is_empty() {
+            bail!("Rekeying failed for {}", failed.join(", "))
+        }
+        Ok(())
+    }
+
+    fn report_recipients(
+        sops_config: &SopsConfig,
+        path: &Path,
+        before: &BTreeSet<String>,
+        after: &BTreeSet<String>,
+        dry_run: bool,
+    ) {
+        let name = |recipient: &String| match sops_config.key_name(recipient) {
+            Some(name) => format!("{name} ({recipient})"),
+            None => recipient.clone(),
+        };
+        let prefix = if dry_run { "[dry-run] would " } else { "" };
+        for recipient in after.difference(before) {
+            info!("🔸 {}: {prefix}add {}", path.display(), name(recipient));
+        }
+        for recipient in before.difference(after) {
+            info!("🔸 {}: {prefix}remove {}", path.display(), name(recipient));
+        }
+        if before == after {
+            info!("✅ {}: recipients unchanged", path.display());
+        }
+    }
+}
@@ -1,58 +1,163 @@
-use std::{
-    fs::{self, File, OpenOptions},
-    io::{BufRead, BufReader, Write},
-    path::PathBuf,
-};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::{Fingerprint, HashAlg, PublicKey};
 use tracing::{info, warn};
 
+use crate::helpers::{
+    self,
+    known_hosts::{self, Key, KnownHosts},
+};
+
+#[derive(Clone)]
 pub struct Info {
     known_hosts_path: PathBuf,
+    dry_run: bool,
 }
 
 impl Info {
-    pub fn new(known_hosts_path: PathBuf) -> Self {
-        Self { known_hosts_path }
+    pub fn new(known_hosts_path: PathBuf, dry_run: bool) -> Self {
+        Self {
+            known_hosts_path,
+            dry_run,
+        }
     }
 
-    pub fn update_knowing_hosts(&self, destination: &str, port: &str, pk: &str) -> Result<bool> {
-        info!("🔁 Update ssh knowing host");
-        let known_lines: Vec<String> = BufReader::new(File::open(&self.known_hosts_path)?)
-            .lines()
-            .collect::<Result<_, _>>()?;
-        let full_entry = format!("[{}]:{} {}", destination, port, pk);
-        let host_prefix = format!("[{}]:{}", destination, port);
-
-        if known_lines.iter().any(|line| line == &full_entry) {
-            warn!("❗ Remote host is already known");
-            return Ok(false);
+    /// Check `pk` against known_hosts before trusting it: a new host key is confirmed from its
+    /// fingerprint, a changed one is refused unless confirmed, or expected after a `reinstall`.
+    /// An `expected` fingerprint is trusted without asking, any other key aborts.
+    pub fn verify_host_key(
+        &self,
+        host: &str,
+        port: &str,
+        pk: &str,
+        reinstall: bool,
+        expected: Option<&str>,
+    ) -> Result<()> {
+        let port: u16 = port.parse().context(format!("Invalid ssh port {port}"))?;
+        let pattern = known_hosts::host_pattern(host, port);
+        let key = Key::parse(pk)?;
+        let offered = fingerprint(pk)?;
+        let known_hosts = KnownHosts::load(&self.known_hosts_path)?;
+        if known_hosts.is_revoked(&key) {
+            bail!("Host key {offered} of {pattern} is @revoked in known hosts")
+        }
+        let mut known = known_hosts.keys(&pattern);
+        if port == 22 {
+            known.extend(known_hosts.keys(&format!("[{host}]:22")));
         }
 
-        if known_lines.iter().any(|line| line.contains(&host_prefix)) {
-            info!("🔸 Remote host key has been updated in knowing hosts");
-            let updated_lines: Vec<String> = known_lines
-                .into_iter()
-                .map(|line| {
-                    if line.contains(&host_prefix) {
-                        full_entry.clone()
-                    } else {
-                        line
-                    }
-                })
-                .collect();
-
-            fs::write(&self.known_hosts_path, updated_lines.join("\n") + "\n")?;
+        let changed: Vec<&Key> = known
+            .iter()
+            .filter(|known| known.key_type == key.key_type && **known != key)
+            .collect();
+        let pinned = match expected {
+            Some(expected) => {
+                let expected: Fingerprint = expected
+                    .parse()
+                    .map_err(|err| anyhow!("Invalid host key fingerprint {expected}: {err}"))?;
+                let actual = PublicKey::from_openssh(pk)
+                    .context("Host public key parsing failed")?
+                    .fingerprint(expected.algorithm());
+                if actual == expected {
+                    true
+                } else if reinstall {
+                    // The pinned key may be the one of the rescue or installer system, the
+                    // offered key must then be known or confirmed
+                    warn!(
+                        "❗ Host key {actual} of {pattern} isn't the expected {expected} anymore"
+                    );
+                    false
+                } else {
+                    bail!(
+                        "Host key {actual} of {pattern} doesn't match the expected {expected}, connection aborted"
+                    )
+                }
+            }
+            None => false,
+        };
+        if pinned {
+            info!("✅ Host key {offered} of {pattern} matches the expected fingerprint");
+            if !changed.is_empty() && !known.contains(&key) {
+                warn!("❗ The expected host key replaces the one known for {pattern}");
+            }
+        } else if known.contains(&key) {
+            info!("✅ Host key {offered} of {pattern} is known");
+        } else if changed.is_empty() {
+            warn!("❗ {pattern} is unknown, its host key fingerprint is {offered}");
+            if !helpers::ask_confirmation_or(
+                "host-key",
+                &format!("Do you trust the host key {offered} of {pattern}?"),
+                false,
+            )? {
+                bail!("Host key {offered} of {pattern} isn't trusted")
+            }
+        } else if reinstall && expected.is_none() {
+            warn!("❗ Host key of {pattern} changed by the reinstall, now {offered}");
         } else {
-            info!("🔸 Remote host key has been add in knowing hosts");
+            warn!("❗ Host key of {pattern} has CHANGED, someone could be eavesdropping");
+            for known in changed {
+                warn!("🔸 Known host key: {}", fingerprint(&known.to_string())?);
+            }
+            warn!("🔸 Offered host key: {offered}");
+            if !helpers::ask_confirmation_or(
+                "host-key-changed",
+                "Is this host key change expected (e.g. the host has been reinstalled)?",
+                false,
+            )? {
+                bail!("Host key of {pattern} changed, connection refused")
+            }
+        }
+        self.update_known_hosts(host, &port.to_string(), pk)?;
+        Ok(())
+    }
 
-            let mut file = OpenOptions::new()
-                .append(true)
-                .open(&self.known_hosts_path)
-                .with_context(|| format!("Failed to open {}", self.known_hosts_path.display()))?;
-            writeln!(file, "{}", full_entry)?;
+    /// Trust `pk` for `host:port`, replacing its previous key of the same type.
+    pub fn update_known_hosts(&self, host: &str, port: &str, pk: &str) -> Result<bool> {
+        info!("🔁 Update ssh known hosts");
+        let port = port.parse().context(format!("Invalid ssh port {port}"))?;
+        let pattern = known_hosts::host_pattern(host, port);
+        let key = Key::parse(pk)?;
+        let mut known_hosts = KnownHosts::load(&self.known_hosts_path)?;
+        if known_hosts.is_revoked(&key) {
+            bail!("Host key of {pattern} is @revoked in known hosts")
+        }
+        let replaced = known_hosts
+            .keys(&pattern)
+            .iter()
+            .any(|known| known.key_type == key.key_type);
+        // Earlier versions wrote `[host]:22`, which OpenSSH never looks up
+        let legacy = port == 22 && known_hosts.remove(&format!("[{host}]:22"), Some(&key.key_type));
+        if !known_hosts.replace(&pattern, &key) && !legacy {
+            info!("✅ {pattern} is already known");
+            return Ok(false);
         }
 
+        if self.dry_run {
+            info!(
+                "🔸 [dry-run] {pattern} {key} would be written into {}",
+                self.known_hosts_path.display()
+            );
+            return Ok(true);
+        }
+        if let Some(backup) = known_hosts.save()? {
+            info!(
+                "🔸 Previous known hosts backed up into {}",
+                backup.display()
+            );
+        }
+        match replaced {
+            true => info!("🔸 Remote host key has been updated in known hosts"),
+            false => info!("🔸 Remote host key has been added in known hosts"),
+        }
         Ok(true)
     }
 }
+
+/// SHA256 fingerprint of an OpenSSH public key, as `ssh-keygen -l` prints it.
+fn fingerprint(pk: &str) -> Result<String> {
+    Ok(PublicKey::from_openssh(pk)
+        .context("Host public key parsing failed")?
+        .fingerprint(HashAlg::Sha256)
+        .to_string())
+}
@@ -4,22 +4,21 @@ use std::{
 };
 
 use anyhow::{Context, Result, anyhow};
+use regex::Regex;
 use tracing::{info, warn};
 
-use crate::helpers::{self};
+use crate::helpers::{self, sops::SopsConfig};
 
 impl super::Host {
-    pub fn update_hardware_config(&self, contents: &Vec<u8>) -> Result<()> {
+    pub fn update_hardware_config(&self, contents: &[u8]) -> Result<()> {
         info!("🔁 Update hardware config");
         let repo = self.get_repo()?;
         let host = repo.get_host();
-        let hardware_config_path = format!(
-            "{}/hosts/{}/hardware-configuration.nix",
-            repo.path.display(),
-            host
-        );
+        let hardware_config_path = repo
+            .path
+            .join(format!("hosts/{host}/hardware-configuration.nix"));
 
-        fs::write(hardware_config_path, contents)?;
+        helpers::file::write(&hardware_config_path, contents, self.dry_run)?;
         Ok(())
     }
 
@@ -38,14 +37,14 @@ impl super::Host {
             .context("Reading lines failed")?;
 
         for (index, line) in lines.iter().enumerate() {
-            if line.trim_start().starts_with(&line_prefix) {
+            if line.trim_start().starts_with(line_prefix) {
                 if line.trim() == new_line.trim() {
                     warn!("❗ Disk device was already set for {host}");
                     return Ok(false);
                 } else {
                     info!("🔸 Update disk device for {host}");
                     lines[index] = new_line.clone();
-                    fs::write(host_path, lines.join("\n"))?;
+                    helpers::file::write(&host_path, lines.join("\n").as_bytes(), self.dry_run)?;
                     return Ok(true);
                 }
             }
@@ -53,96 +52,125 @@ impl super::Host {
         Err(anyhow!("Disk device has not been find"))
     }
 
-    pub fn update_sops(&self, contents: &str) -> Result<bool> {
+    /// Add the host age key to `.sops.yaml`, returns the edited config when it changed.
+    pub fn update_sops(&self, contents: &str) -> Result<Option<SopsConfig>> {
         info!("🔁 Update SOPS");
         let repo = self.get_repo()?;
         let sops_path = repo.path.join(".sops.yaml");
         let host: &str = repo.host.as_ref();
-        let key_line_prefix = format!("- &{host}",);
-        let new_key_line = format!("    - &{host} {}", contents);
-        let new_ref_line = format!("          - *{host}");
-        let file =
-            File::open(&sops_path).context(format!("Opening {} failed", sops_path.display()))?;
-        let mut lines: Vec<String> = BufReader::new(file)
-            .lines()
-            .collect::<Result<_, _>>()
-            .context("Reading lines failed")?;
-
-        let mut key_view_index: Option<usize> = None;
-        let mut keys: Vec<String> = Vec::new();
-
-        let mut ref_view_index: Option<usize> = None;
-        let mut refs: Vec<String> = Vec::new();
-
-        for (index, line) in lines.iter().enumerate() {
-            if line.trim_start().starts_with(&key_line_prefix) {
-                if line.trim() == new_key_line.trim() {
-                    warn!("❗ Key was already into SOPS for {host}");
-                    return Ok(false);
-                } else {
-                    info!("🔸 Update key into SOPS for {host}");
-                    lines[index] = new_key_line.clone();
-                    fs::write(sops_path, lines.join("\n"))?;
-                    return Ok(true);
-                }
+        let mut sops = SopsConfig::parse(
+            &fs::read_to_string(&sops_path)
+                .context(format!("Opening {} failed", sops_path.display()))?,
+        )?;
+
+        let mut changed = match sops.get_key(host)? {
+            Some(key) if key == contents => false,
+            Some(_) => {
+                info!("🔸 Update key into SOPS for {host}");
+                sops.set_key(host, contents)?
             }
-
-            if line.trim() == "users: &age_keys" {
-                key_view_index = Some(index);
-            } else if line.trim() == "- age:" {
-                ref_view_index = Some(index);
+            None => {
+                info!("🔸 Add key into SOPS for {host}");
+                sops.set_key(host, contents)?
             }
-
-            if let Some(key_index) = key_view_index {
-                if key_index == index - 1 && line.trim_start().starts_with("- &") {
-                    keys.push(line.trim().to_string());
-                    key_view_index = Some(index);
+        };
+
+        let host_dirs = self.get_host_dirs()?;
+        for rule in sops.creation_rules() {
+            let path_regex = rule.path_regex.as_deref().unwrap_or("(any path)");
+            match Self::rule_scope(rule.path_regex.as_deref(), repo.get_host(), &host_dirs)? {
+                RuleScope::Host => {}
+                RuleScope::OtherHost => continue,
+                // Such rules may protect secrets hosts must not read (operators, users, ...)
+                RuleScope::Other => {
+                    if !helpers::ask_confirmation_or(
+                        &format!("sops-rule-{}", rule.index),
+                        &format!(
+                            "Should {host} decrypt the secrets of creation rule {path_regex}?"
+                        ),
+                        false,
+                    )? {
+                        info!("⏭️ Creation rule {path_regex} left without {host}");
+                        continue;
+                    }
                 }
             }
-
-            if let Some(ref_index) = ref_view_index {
-                if ref_index == index - 1 && line.trim_start().starts_with("- *") {
-                    refs.push(line.trim().to_string());
-                    ref_view_index = Some(index);
-                }
+            if sops.add_recipient(rule.index, host)? {
+                info!("🔸 Add {host} into creation rule {path_regex}");
+                changed = true;
             }
         }
 
-        let mut new_key_added = false;
-        if !keys.iter().any(|key| key == new_key_line.trim()) {
-            if let Some(key_index) = key_view_index {
-                lines.insert(key_index + 1, new_key_line.to_string());
-                new_key_added = true;
-            }
+        if !changed {
+            warn!("❗ Key was already into SOPS for {host}");
+            return Ok(None);
         }
+        helpers::file::write(&sops_path, sops.to_string().as_bytes(), self.dry_run)?;
+        Ok(Some(sops))
+    }
 
-        let mut new_ref_added = false;
-        if new_key_added && !refs.iter().any(|r#ref| r#ref == new_ref_line.trim()) {
-            if let Some(ref_index) = ref_view_index {
-                lines.insert(ref_index + 2, new_ref_line.to_string());
-                new_ref_added = true;
-            }
+    /// Remove the host age key from `.sops.yaml`, returns the edited config when it changed.
+    pub fn remove_sops(&self) -> Result<Option<SopsConfig>> {
+        info!("🔁 Remove key from SOPS");
+        let repo = self.get_repo()?;
+        let sops_path = repo.path.join(".sops.yaml");
+        let host: &str = repo.host.as_ref();
+        let mut sops = SopsConfig::parse(
+            &fs::read_to_string(&sops_path)
+                .context(format!("Opening {} failed", sops_path.display()))?,
+        )?;
+        // Recipients first, scalar lists are matched against the key itself
+        let removed_recipient = sops.remove_recipient(host)?;
+        let removed_key = sops.remove_key(host)?;
+        if !removed_key && !removed_recipient {
+            warn!("❗ Key was not into SOPS for {host}");
+            return Ok(None);
         }
+        info!("🔸 Key removed from SOPS for {host}");
+        helpers::file::write(&sops_path, sops.to_string().as_bytes(), self.dry_run)?;
+        Ok(Some(sops))
+    }
 
-        if new_key_added && new_ref_added {
-            info!("🔁 New key added into SOPS for {host}");
-            fs::write(sops_path, lines.join("\n"))?;
-            Ok(true)
-        } else {
-            Err(anyhow!("Failed to update SOPS file"))
+    fn get_host_dirs(&self) -> Result<Vec<String>> {
+        let hosts_path = self.get_repo()?.path.join("hosts");
+        if !hosts_path.is_dir() {
+            return Ok(Vec::new());
         }
+        Ok(fs::read_dir(hosts_path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect())
     }
 
-    pub fn update_encrypt_file_keys(&self) -> Result<()> {
-        info!("🔁 Update encryt file with remote age key");
-        let repo = self.get_repo()?;
-        let encryt_file_path = format!("{}/nixos/common/secrets.yaml", repo.path.display());
-        let command = format!(
-            "SOPS_AGE_KEY=$(ssh-to-age -private-key -i ~/.ssh/id_ed25519) SOPS_CONFIG={}/.sops.yaml sops updatekeys -y {}",
-            repo.path.display(),
-            encryt_file_path
-        );
-        tracing::info!("🔸 {command}");
-        helpers::command::run(&command)
+    /// Whose secrets a creation rule protects, the ones of `host` when it matches its
+    /// secrets or has no `path_regex`.
+    fn rule_scope(path_regex: Option<&str>, host: &str, host_dirs: &[String]) -> Result<RuleScope> {
+        let Some(path_regex) = path_regex else {
+            return Ok(RuleScope::Host);
+        };
+        let regex = Regex::new(path_regex)
+            .context(format!("Invalid creation rule path_regex: {path_regex}"))?;
+        let secrets_path = |host: &str| format!("hosts/{host}/secrets.yaml");
+        if regex.is_match(&secrets_path(host)) {
+            return Ok(RuleScope::Host);
+        }
+        Ok(
+            match host_dirs
+                .iter()
+                .filter(|dir| *dir != host)
+                .any(|dir| regex.is_match(&secrets_path(dir)))
+            {
+                true => RuleScope::OtherHost,
+                false => RuleScope::Other,
+            },
+        )
     }
 }
+
+/// Secrets a creation rule of `.sops.yaml` protects, seen from the bootstrapped host.
+enum RuleScope {
+    Host,
+    OtherHost,
+    Other,
+}
@@ -1,55 +1,207 @@
 use anyhow::{Result, bail};
+use clap::Parser;
 use tracing::{info, warn};
 
+use crate::{
+    cli::{BootstrapArgs, Cli, Command, DeployArgs},
+    helpers::{
+        extra_files::ExtraFiles,
+        prompt::{self, Prompter},
+    },
+    state::State,
+};
+
+mod cli;
 mod helpers;
 mod local;
 mod remote;
+mod state;
 
 fn main() -> Result<()> {
+    if helpers::askpass::run_helper()? {
+        return Ok(());
+    }
     tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    set_prompter(&cli)?;
     info!("🚀 Welcome to nix-bootstrap !");
     info!("🔸 A tool to install nixos configuration with sops keys update");
-    let is_remote_system_running_on_image =
-        helpers::ask_confirmation("Does remote host system is running on an installer image?")?;
 
-    let mut local = local::Host::new()?;
-    let mut remote = remote::Host::new(&local)?;
-    let hardware_config = remote.get_hardware_config()?;
-    let disk_device = remote.get_disk_device()?;
+    let mut local = local::Host::new(
+        cli.flake.flake.clone(),
+        cli.sops_identity.clone(),
+        cli.known_hosts.clone(),
+        cli.dry_run,
+    )?;
+    if cli.dry_run {
+        warn!("🔸 Dry-run: nothing will be changed, actions are only reported");
+    }
+    match &cli.command {
+        Command::Bootstrap(args) => bootstrap(&cli, &mut local, args),
+        Command::Hardware => hardware(&cli, &mut local),
+        Command::Disk => disk(&cli, &mut local),
+        Command::Rekey { remove } => rekey(&cli, &mut local, *remove),
+        Command::Deploy(args) => deploy(&cli, &mut local, args),
+        Command::Rebuild => rebuild(&cli, &mut local),
+    }
+}
+
+fn set_prompter(cli: &Cli) -> Result<()> {
+    let mut prompter: Box<dyn Prompter> = Box::new(prompt::Interactive::new(cli.non_interactive));
+    if let Some(answers) = &cli.answers {
+        prompter = Box::new(prompt::Replay::load(answers, prompter)?);
+    }
+    if let Some(record) = &cli.record {
+        prompter = Box::new(prompt::Recorder::new(record.clone(), prompter));
+    }
+    prompt::set_prompter(prompter)
+}
+
+fn bootstrap(cli: &Cli, local: &mut local::Host, args: &BootstrapArgs) -> Result<()> {
+    let mut state = State::open(args.state_file.clone(), args.resume, local.dry_run)?;
+    if local.flake_path.is_none() {
+        local.flake_path = state.flake.clone();
+    }
+    let destination = match &cli.remote.destination {
+        Some(destination) => Some(remote::split_destination(destination)?.0),
+        None => None,
+    };
+    state.check_target(cli.flake.host.as_deref(), destination.as_deref())?;
+    // Recorded right away so that a resumed run can tell whose state it is
+    state.update(|state| {
+        state.host = state.host.take().or_else(|| cli.flake.host.clone());
+        state.remote.destination = state.remote.destination.take().or(destination);
+    })?;
+    let mut remote_args = cli.remote.clone();
+    remote_args.fill(&state.remote);
+
+    let is_remote_system_running_on_image = match args.installer.or(state.installer) {
+        Some(installer) => installer,
+        None => helpers::ask_confirmation(
+            "installer",
+            "Does remote host system is running on an installer image?",
+        )?,
+    };
+    state.update(|state| state.installer = Some(is_remote_system_running_on_image))?;
+    let is_installed = state.run_nixos_anywhere && state.reboot;
+
+    if is_remote_system_running_on_image && !is_installed {
+        let installer_host = args
+            .installer_host
+            .clone()
+            .or_else(|| state.installer_host.clone());
+        local.set_nix_config(true, true, installer_host.as_deref())?;
+        let repo = local.get_repo()?;
+        state.update(|state| {
+            state.installer_host = Some(repo.host.clone());
+            state.flake = Some(repo.path.clone());
+        })?;
+    } else {
+        set_final_nix_config(cli, local, &mut state)?;
+    }
+
+    let mut remote = match is_installed {
+        true => remote::Host::new_installed(local, &remote_args)?,
+        false => remote::Host::new(local, &remote_args)?,
+    };
+    state.update(|state| state.remote = remote.args.clone())?;
 
-    if is_remote_system_running_on_image {
+    let hardware_config = match &state.hardware_file {
+        Some(hardware_file) if state.get_hardware_config => {
+            info!("⏭️ Hardware configuration already fetched");
+            remote.config.hardware_file = Some(hardware_file.as_bytes().to_vec());
+            true
+        }
+        _ => remote.get_hardware_config()?,
+    };
+    if hardware_config {
+        let hardware_file = String::from_utf8(remote.config.get_hardware_file()?.to_vec())?;
+        state.update(|state| {
+            state.hardware_file = Some(hardware_file);
+            state.get_hardware_config = true;
+        })?;
+    }
+
+    let disk_device = match &state.disk_device {
+        Some(disk_device) if state.select_disk_device => {
+            info!("⏭️ Disk device already selected");
+            remote.config.disk_device = Some(disk_device.clone());
+            true
+        }
+        _ => remote.get_disk_device()?,
+    };
+    if disk_device {
+        let disk_device = remote.config.get_disk_device()?.clone();
+        state.update(|state| {
+            state.disk_device = Some(disk_device);
+            state.select_disk_device = true;
+        })?;
+    }
+
+    if is_remote_system_running_on_image && !is_installed {
         info!("🆕 Remote host system is running on an image");
         warn!("🔸 SSH access must be available");
         warn!("🔸 Password must be set");
-        local.set_nix_config(true, true)?;
         if hardware_config {
             local.update_hardware_config(remote.config.get_hardware_file()?)?;
         }
         if disk_device {
             local.update_disk_config(&remote.config.get_disk_device()?.name)?;
         }
-        local.get_repo()?.config_changes()?;
-        if !local.deploy_nixos_anywhere(&remote)? {
-            bail!("Couldn't continue if you don't deploy this from iso")
+        local.get_repo()?.config_changes(local.dry_run)?;
+        if state.run_nixos_anywhere {
+            info!("⏭️ nixos-anywhere already deployed");
+        } else {
+            if !local.confirm_nixos_anywhere()? {
+                bail!("Couldn't continue if you don't deploy this from iso")
+            }
+            let (extra_files, host_pk) = prepare_extra_files(local, &mut remote, None)?;
+            local.deploy_nixos_anywhere(&remote, extra_files.as_ref())?;
+            if let Some(host_pk) = host_pk {
+                local
+                    .ssh
+                    .update_known_hosts(&remote.hostname, &remote.port, &host_pk)?;
+            }
+            state.update(|state| state.run_nixos_anywhere = true)?;
         }
-        helpers::ask_confirmation("Does remote host has reboot?")?;
-        remote.reconnect(&local)?;
+        helpers::ask_confirmation("reboot", "Does remote host has reboot?")?;
+        state.update(|state| state.reboot = true)?;
+        set_final_nix_config(cli, local, &mut state)?;
+        remote.reconnect(local, true)?;
+        state.update(|state| state.remote = remote.args.clone())?;
     }
 
     info!("🔄 Remote host system is running on an config");
     warn!("🔸 SSH access must be available");
     warn!("🔸 Root privileges must be available");
 
-    if helpers::ask_confirmation("Do you want to use nix config locally?")? {
-        local.set_nix_config(false, true)?;
-    } else {
-        local.set_nix_config(false, false)?;
-    }
-
-    let age_key = remote.get_age_key()?;
+    let mut sops_config = None;
+    let age_key = match &state.age_key {
+        Some(age_key) if state.add_age_key => {
+            info!("⏭️ Age key already added into SOPS");
+            remote.config.age_pk = Some(age_key.clone());
+            true
+        }
+        _ => {
+            let age_key = remote.get_age_key()?;
+            if age_key {
+                sops_config = local.update_sops(remote.config.get_age_key()?)?;
+                let age_key = remote.config.get_age_key()?.to_string();
+                state.update(|state| {
+                    state.age_key = Some(age_key);
+                    state.add_age_key = true;
+                })?;
+            }
+            age_key
+        }
+    };
     if age_key {
-        local.update_sops(remote.config.get_age_key()?)?;
-        local.update_encrypt_file_keys()?;
+        if state.rekey_secrets {
+            info!("⏭️ Secrets already rekeyed");
+        } else {
+            local.update_encrypt_file_keys(sops_config.as_ref())?;
+            state.update(|state| state.rekey_secrets = true)?;
+        }
     }
     if hardware_config {
         local.update_hardware_config(remote.config.get_hardware_file()?)?;
@@ -57,8 +209,179 @@ fn main() -> Result<()> {
     if disk_device {
         local.update_disk_config(&remote.config.get_disk_device()?.name)?;
     }
-    local.get_repo()?.config_changes()?;
-    local.deploy_nixos_rebuild(&remote)?;
+    local.get_repo()?.config_changes(local.dry_run)?;
+    if state.run_nixos_rebuild {
+        info!("⏭️ nixos-rebuild already deployed");
+    } else if local.deploy_nixos_rebuild(&mut remote)? {
+        state.update(|state| state.run_nixos_rebuild = true)?;
+    }
 
-    Ok(info!("🚀 Reboot your remote host and enjoy !"))
+    info!("🚀 Reboot your remote host and enjoy !");
+    Ok(())
+}
+
+fn set_final_nix_config(cli: &Cli, local: &mut local::Host, state: &mut State) -> Result<()> {
+    let use_path = local.flake_path.is_some()
+        || helpers::ask_confirmation("local-config", "Do you want to use nix config locally?")?;
+    let host = cli.flake.host.clone().or_else(|| state.host.clone());
+    local.set_nix_config(false, use_path, host.as_deref())?;
+    let repo = local.get_repo()?;
+    state.update(|state| {
+        state.host = Some(repo.host.clone());
+        if use_path {
+            state.flake = Some(repo.path.clone());
+        }
+    })
+}
+
+fn hardware(cli: &Cli, local: &mut local::Host) -> Result<()> {
+    local.set_nix_config(false, true, cli.flake.host.as_deref())?;
+    let mut remote = remote::Host::new(local, &cli.remote)?;
+    remote.fetch_hardware_config()?;
+    local.update_hardware_config(remote.config.get_hardware_file()?)?;
+    local.get_repo()?.config_changes(local.dry_run)
+}
+
+fn disk(cli: &Cli, local: &mut local::Host) -> Result<()> {
+    local.set_nix_config(false, true, cli.flake.host.as_deref())?;
+    let mut remote = remote::Host::new(local, &cli.remote)?;
+    remote.select_disk_device()?;
+    local.update_disk_config(&remote.config.get_disk_device()?.name)?;
+    local.get_repo()?.config_changes(local.dry_run)
+}
+
+fn rekey(cli: &Cli, local: &mut local::Host, remove: bool) -> Result<()> {
+    local.set_nix_config(false, true, cli.flake.host.as_deref())?;
+    if remove {
+        let sops_config = local.remove_sops()?;
+        local.update_encrypt_file_keys(sops_config.as_ref())?;
+        return local.get_repo()?.config_changes(local.dry_run);
+    }
+    let mut remote = remote::Host::new(local, &cli.remote)?;
+    remote.fetch_age_key()?;
+    let sops_config = local.update_sops(remote.config.get_age_key()?)?;
+    local.update_encrypt_file_keys(sops_config.as_ref())?;
+    local.get_repo()?.config_changes(local.dry_run)
+}
+
+fn deploy(cli: &Cli, local: &mut local::Host, args: &DeployArgs) -> Result<()> {
+    local.set_nix_config(false, true, cli.flake.host.as_deref())?;
+    let mut remote = remote::Host::new(local, &cli.remote)?;
+    // Asked first: a pre-generated key is added into SOPS before the install
+    if !local.confirm_nixos_anywhere()? {
+        return Ok(());
+    }
+    let (extra_files, host_pk) = prepare_extra_files(local, &mut remote, Some(args))?;
+    local.deploy_nixos_anywhere(&remote, extra_files.as_ref())?;
+    if let Some(host_pk) = host_pk {
+        local
+            .ssh
+            .update_known_hosts(&remote.hostname, &remote.port, &host_pk)?;
+    }
+    Ok(())
+}
+
+/// Extra files of nixos-anywhere holding the host keys to install, pre-generated or kept as
+/// asked by `args` or the host inventory, with the ed25519 public key the host will have.
+/// `deploy` gives its `args` and reaches the system being reinstalled, whose keys can be
+/// backed up; `bootstrap` reaches an installer, only an existing backup is restored.
+fn prepare_extra_files(
+    local: &local::Host,
+    remote: &mut remote::Host,
+    args: Option<&DeployArgs>,
+) -> Result<(Option<ExtraFiles>, Option<String>)> {
+    let options = local
+        .get_repo()?
+        .get_host_entry()
+        .map(|entry| entry.deploy.clone())
+        .unwrap_or_default();
+    let pregenerate_host_key = args.is_some_and(|args| args.pregenerate_host_key)
+        || options.pregenerate_host_key.unwrap_or(false);
+    let keep_host_keys =
+        args.is_some_and(|args| args.keep_host_keys) || options.keep_host_keys.unwrap_or(false);
+    if pregenerate_host_key && keep_host_keys {
+        bail!("Host keys can't be both pre-generated and kept")
+    }
+    if !pregenerate_host_key && !keep_host_keys {
+        return Ok((None, None));
+    }
+
+    let files = ExtraFiles::new(
+        args.and_then(|args| args.persist_path.as_deref())
+            .or(options.persist_path.as_deref()),
+    )?;
+    if pregenerate_host_key {
+        let host_pk = local.pregenerate_host_key(&files)?;
+        return Ok((Some(files), Some(host_pk)));
+    }
+    if args.is_some() {
+        // A backup of other keys (pre-generated, host reinstalled since) would change the
+        // identity of the host
+        match local.host_keys_backup_matches(&remote.ssh_pk)? {
+            Some(true) => info!("⏭️ ssh host keys already backed up"),
+            Some(false) => {
+                warn!("❗ Backed up ssh host keys aren't the ones of the remote host");
+                if !helpers::ask_confirmation(
+                    "host-keys-backup-replace",
+                    "Do you want to replace the backup with the current host keys?",
+                )? {
+                    bail!("Host keys can't be kept, the backup belongs to another key")
+                }
+                local.backup_host_keys(&remote.fetch_host_keys()?)?;
+            }
+            None => local.backup_host_keys(&remote.fetch_host_keys()?)?,
+        }
+    } else if !local.has_host_keys_backup()? {
+        warn!("❗ No ssh host keys backup, the host gets new keys from the install");
+        return Ok((None, None));
+    }
+    let host_pk = local.restore_host_keys(&files)?;
+    local.get_repo()?.config_changes(local.dry_run)?;
+    Ok((Some(files), host_pk))
+}
+
+fn rebuild(cli: &Cli, local: &mut local::Host) -> Result<()> {
+    local.set_nix_config(false, true, cli.flake.host.as_deref())?;
+    let mut remote = remote::Host::new(local, &cli.remote)?;
+    push_kept_host_keys(local, &mut remote)?;
+    local.deploy_nixos_rebuild(&mut remote)?;
+    Ok(())
+}
+
+/// Put the backed up ssh host keys back onto a host whose inventory keeps them, when it has
+/// been reinstalled outside nix-bootstrap with other keys.
+fn push_kept_host_keys(local: &local::Host, remote: &mut remote::Host) -> Result<()> {
+    let options = local
+        .get_repo()?
+        .get_host_entry()
+        .map(|entry| entry.deploy.clone())
+        .unwrap_or_default();
+    if !options.keep_host_keys.unwrap_or(false)
+        || local.host_keys_backup_matches(&remote.ssh_pk)? != Some(false)
+    {
+        return Ok(());
+    }
+    warn!("❗ Remote host doesn't have its backed up ssh host keys anymore");
+    if !helpers::ask_confirmation_or(
+        "host-keys-push",
+        "Do you want to put the backed up host keys back?",
+        false,
+    )? {
+        return Ok(());
+    }
+    let files = ExtraFiles::new(options.persist_path.as_deref())?;
+    let Some(host_pk) = local.restore_host_keys(&files)? else {
+        return Ok(());
+    };
+    remote.push_extra_files(&files)?;
+    if local.dry_run {
+        info!("🔸 [dry-run] sshd would be restarted");
+    } else {
+        // Running sessions survive, new ones are offered the restored key
+        remote.run_privileged("systemctl restart sshd")?;
+    }
+    local
+        .ssh
+        .update_known_hosts(&remote.hostname, &remote.port, &host_pk)?;
+    local.get_repo()?.config_changes(local.dry_run)
 }
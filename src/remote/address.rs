@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    net::{Ipv6Addr, SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use tracing::{info, warn};
+
+/// Split a `[host]:port` destination, brackets are dropped from `[host]` alone.
+/// Other destinations are kept as-is, bare IPv6 literals included.
+pub fn split_destination(destination: &str) -> Result<(String, Option<u16>)> {
+    let Some(bracketed) = destination.strip_prefix('[') else {
+        return Ok((destination.to_string(), None));
+    };
+    let (host, rest) = bracketed
+        .split_once(']')
+        .ok_or_else(|| anyhow!("Invalid destination {destination}"))?;
+    let port = match rest {
+        "" => None,
+        rest => Some(
+            rest.strip_prefix(':')
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid port in destination {destination}"))?,
+        ),
+    };
+    Ok((host.to_string(), port))
+}
+
+/// `host:port`, with the host bracketed when it is an IPv6 literal.
+pub fn format(host: &str, port: u16) -> String {
+    match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    }
+}
+
+/// Every address of the host, IPv6 literals may carry a `%zone` (interface name or index).
+fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if let Some((ip, zone)) = host.split_once('%')
+        && let Ok(ip) = ip.parse::<Ipv6Addr>()
+    {
+        let scope_id = match zone.parse() {
+            Ok(scope_id) => scope_id,
+            Err(_) => fs::read_to_string(format!("/sys/class/net/{zone}/ifindex"))
+                .ok()
+                .and_then(|index| index.trim().parse().ok())
+                .ok_or_else(|| anyhow!("Unknown network interface {zone} of {host}"))?,
+        };
+        return Ok(vec![SocketAddr::V6(SocketAddrV6::new(
+            ip, port, 0, scope_id,
+        ))]);
+    }
+    Ok((host, port)
+        .to_socket_addrs()
+        .context(format!("Could not resolve address {}", format(host, port)))?
+        .collect())
+}
+
+/// Connect to the first address of the host answering, trying them all in turn.
+pub fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let addrs = resolve(host, port)?;
+    if addrs.is_empty() {
+        bail!("Could not resolve address {}", format(host, port))
+    }
+    let mut errors = Vec::new();
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                if !errors.is_empty() {
+                    info!("🔸 Connected to {addr}");
+                }
+                return Ok(stream);
+            }
+            Err(err) => {
+                warn!("❗ Connecting to {addr} failed: {err}");
+                errors.push(format!("{addr} ({err})"));
+            }
+        }
+    }
+    bail!(
+        "Failed to connect to {}: {}",
+        format(host, port),
+        errors.join(", ")
+    )
+}
@@ -0,0 +1,373 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use ssh_key::PrivateKey;
+use ssh2::{KeyboardInteractivePrompt, Prompt, Session};
+use tracing::info;
+
+use crate::{
+    cli::RemoteArgs,
+    helpers::{self, askpass, prompt},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Agent,
+    #[serde(rename = "password")]
+    Passwd,
+    #[serde(rename = "identity-file")]
+    IdentityFile,
+    #[serde(rename = "keyboard-interactive")]
+    KeyboardInteractive,
+}
+
+impl AuthMethod {
+    /// Name of the method in the server `auth_methods()` list.
+    fn server_method(&self) -> &'static str {
+        match self {
+            AuthMethod::Agent | AuthMethod::IdentityFile => "publickey",
+            AuthMethod::Passwd => "password",
+            AuthMethod::KeyboardInteractive => "keyboard-interactive",
+        }
+    }
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            AuthMethod::Agent => "agent",
+            AuthMethod::Passwd => "password",
+            AuthMethod::IdentityFile => "identity-file",
+            AuthMethod::KeyboardInteractive => "keyboard-interactive",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "agent" => Ok(AuthMethod::Agent),
+            "password" => Ok(AuthMethod::Passwd),
+            "identity-file" => Ok(AuthMethod::IdentityFile),
+            "keyboard-interactive" => Ok(AuthMethod::KeyboardInteractive),
+            _ => Err(format!("Invalid authentication method: {}", s)),
+        }
+    }
+}
+
+/// Secrets given during authentication, kept to reconnect without asking again.
+#[derive(Default)]
+pub struct Credentials {
+    pub(super) password: Option<String>,
+    passphrase: Option<String>,
+    /// Password of sudo, asked on the first privileged command
+    pub(super) sudo_password: Option<String>,
+}
+
+impl super::Host {
+    /// Environment letting the ssh commands spawned for the host answer their prompts from
+    /// the credentials of the session. The server must be kept alive while they run, it is
+    /// None (and the environment empty) when nothing has been typed.
+    pub fn askpass_envs(&self) -> Result<(Option<askpass::Server>, askpass::Envs)> {
+        let server = askpass::Server::start(askpass::Secrets {
+            logins: vec![
+                format!("{}@{}", self.user, self.hostname),
+                format!("{}@{}", self.user, self.destination),
+            ],
+            password: self.credentials.password.clone(),
+            identity_file: self
+                .args
+                .identity_file
+                .as_deref()
+                .and_then(|path| Path::new(path).file_name())
+                .map(|name| name.to_string_lossy().to_string()),
+            passphrase: self.credentials.passphrase.clone(),
+        })?;
+        let envs = match &server {
+            Some(server) => server.envs()?,
+            None => Vec::new(),
+        };
+        Ok((server, envs))
+    }
+}
+
+/// Identity files tried when none is set for the host.
+const DEFAULT_IDENTITY_FILES: [&str; 3] = ["~/.ssh/id_ed25519", "~/.ssh/id_ecdsa", "~/.ssh/id_rsa"];
+
+/// Answers the keyboard-interactive challenges (password, OTP, ...) through the prompter.
+struct InteractivePrompter<'p> {
+    prefix: &'p str,
+    /// Challenges asked so far, numbering the question id of each one
+    challenges: usize,
+    /// Answer of the password challenge, kept with the credentials
+    password: Option<String>,
+    error: Option<anyhow::Error>,
+}
+
+impl KeyboardInteractivePrompt for InteractivePrompter<'_> {
+    fn prompt<'a>(
+        &mut self,
+        _username: &str,
+        instructions: &str,
+        prompts: &[Prompt<'a>],
+    ) -> Vec<String> {
+        if !instructions.trim().is_empty() {
+            info!("🔸 {}", instructions.trim());
+        }
+        let mut answers = Vec::new();
+        for challenge in prompts {
+            self.challenges += 1;
+            let id = format!("{}keyboard-interactive-{}", self.prefix, self.challenges);
+            let text = challenge.text.trim();
+            let answer = match challenge.echo {
+                true => prompt::input(&id, text, None),
+                false => prompt::password(&id, text),
+            };
+            match answer {
+                Ok(answer) => {
+                    if !challenge.echo && text.to_lowercase().contains("password") {
+                        self.password = Some(answer.clone());
+                    }
+                    answers.push(answer)
+                }
+                Err(err) => {
+                    self.error = Some(err);
+                    break;
+                }
+            }
+        }
+        answers
+    }
+}
+
+/// Authenticate `user` with the method of `args`, asked among the ones the server offers
+/// when unset. Question ids are prefixed by `prefix` (e.g. `jump-` for jump hosts).
+pub fn authenticate(
+    sess: &Session,
+    user: &str,
+    args: &mut RemoteArgs,
+    credentials: &mut Credentials,
+    prefix: &str,
+) -> Result<()> {
+    let auth_method = match &args.auth {
+        Some(auth_method) => auth_method.clone(),
+        None => select_auth_method(sess, user, prefix)?,
+    };
+    args.auth = Some(auth_method.clone());
+    match auth_method {
+        AuthMethod::Agent => {
+            info!("🔸 Authentication (ssh) by agent");
+            sess.userauth_agent(user)
+                .map_err(|err| anyhow!("Authentication (ssh) failed by agent: {err}"))?
+        }
+        AuthMethod::Passwd => {
+            let mut i = 0;
+            loop {
+                let password = match credentials.password.take() {
+                    Some(password) if i == 0 => password,
+                    _ => prompt::password(&format!("{prefix}password"), "Enter password (ssh):")?,
+                };
+                match sess.userauth_password(user, &password) {
+                    Ok(_) => {
+                        credentials.password = Some(password);
+                        break;
+                    }
+
+                    Err(_) => {
+                        i += 1;
+                        if i >= 3 {
+                            bail!("Authentication (ssh) failed: too many attempts");
+                        }
+
+                        if !helpers::ask_confirmation_or(
+                            &format!("{prefix}password-retry"),
+                            "Do you want to retry?",
+                            false,
+                        )? {
+                            bail!("Authentication (ssh) failed by password")
+                        }
+                    }
+                };
+            }
+
+            info!("🔸 Authenticating (ssh) by password");
+        }
+        AuthMethod::IdentityFile => {
+            let private_key = get_identity_file(args, prefix)?;
+            userauth_identity_file(sess, user, &private_key, credentials, prefix)?;
+        }
+        AuthMethod::KeyboardInteractive => {
+            info!("🔸 Authentication (ssh) by keyboard-interactive");
+            let mut i = 0;
+            loop {
+                let mut prompter = InteractivePrompter {
+                    prefix,
+                    challenges: 0,
+                    password: None,
+                    error: None,
+                };
+                match sess.userauth_keyboard_interactive(user, &mut prompter) {
+                    Ok(_) => {
+                        credentials.password = credentials.password.take().or(prompter.password);
+                        break;
+                    }
+                    Err(err) => {
+                        if let Some(err) = prompter.error {
+                            return Err(err);
+                        }
+                        i += 1;
+                        if i >= 3 {
+                            bail!("Authentication (ssh) failed: too many attempts");
+                        }
+                        if !helpers::ask_confirmation_or(
+                            &format!("{prefix}keyboard-interactive-retry"),
+                            "Do you want to retry?",
+                            false,
+                        )? {
+                            bail!("Authentication (ssh) failed by keyboard-interactive: {err}")
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !sess.authenticated() {
+        return Err(anyhow!("Authentication (ssh) failed"));
+    }
+    Ok(())
+}
+
+/// Ask among the methods the server accepts, the single one is picked without asking.
+fn select_auth_method(sess: &Session, user: &str, prefix: &str) -> Result<AuthMethod> {
+    let server_methods = sess.auth_methods(user).unwrap_or_default().to_string();
+    let ssh_auth_opts: Vec<AuthMethod> = [
+        AuthMethod::Agent,
+        AuthMethod::Passwd,
+        AuthMethod::IdentityFile,
+        AuthMethod::KeyboardInteractive,
+    ]
+    .into_iter()
+    .filter(|ssh_auth| {
+        server_methods.is_empty()
+            || server_methods
+                .split(',')
+                .any(|method| method == ssh_auth.server_method())
+    })
+    .collect();
+    match ssh_auth_opts.as_slice() {
+        [] => {
+            bail!("No supported authentication method (ssh) offered: {server_methods}")
+        }
+        [ssh_auth] => {
+            info!("🔸 Only {ssh_auth} authentication (ssh) is offered");
+            Ok(ssh_auth.clone())
+        }
+        _ => {
+            let labels: Vec<String> = ssh_auth_opts
+                .iter()
+                .map(|ssh_auth| ssh_auth.to_string())
+                .collect();
+            let selection = prompt::select(
+                &format!("{prefix}auth"),
+                "Select an authentication method (ssh)?",
+                &labels,
+            )?;
+            Ok(ssh_auth_opts
+                .get(selection)
+                .ok_or_else(|| anyhow!("Authentication method (ssh) not found"))?
+                .clone())
+        }
+    }
+}
+
+/// Identity file of the host, else the first default one found, else asked.
+fn get_identity_file(args: &mut RemoteArgs, prefix: &str) -> Result<PathBuf> {
+    let identity_file = match &args.identity_file {
+        Some(identity_file) => identity_file.to_string(),
+        None => match DEFAULT_IDENTITY_FILES
+            .iter()
+            .find(|path| helpers::file::expand_home(path).exists())
+        {
+            Some(path) => path.to_string(),
+            None => prompt::input(
+                &format!("{prefix}identity-file"),
+                "Enter ssh identity file:",
+                None,
+            )?,
+        },
+    };
+    let path = helpers::file::expand_home(&identity_file);
+    if !path.exists() {
+        bail!("Identity file (ssh) {} not found", path.display())
+    }
+    args.identity_file = Some(identity_file);
+    Ok(path)
+}
+
+fn userauth_identity_file(
+    sess: &Session,
+    user: &str,
+    private_key: &Path,
+    credentials: &mut Credentials,
+    prefix: &str,
+) -> Result<()> {
+    info!(
+        "🔸 Authentication (ssh) by identity file {}",
+        private_key.display()
+    );
+    let public_key = PathBuf::from(format!("{}.pub", private_key.display()));
+    let public_key = public_key.exists().then_some(public_key);
+    // Keys in another format than OpenSSH may be encrypted as well
+    let encrypted = PrivateKey::read_openssh_file(private_key)
+        .map(|key| key.is_encrypted())
+        .ok();
+
+    let mut i = 0;
+    loop {
+        let passphrase = match credentials.passphrase.take() {
+            Some(passphrase) if i == 0 => Some(passphrase),
+            _ if i > 0 || encrypted == Some(true) => Some(prompt::password(
+                &format!("{prefix}passphrase"),
+                &format!("Enter passphrase of {}:", private_key.display()),
+            )?),
+            _ => None,
+        };
+        match sess.userauth_pubkey_file(
+            user,
+            public_key.as_deref(),
+            private_key,
+            passphrase.as_deref(),
+        ) {
+            Ok(_) => {
+                credentials.passphrase = passphrase;
+                return Ok(());
+            }
+            Err(err) => {
+                if encrypted == Some(false) {
+                    bail!("Authentication (ssh) failed by identity file: {err}")
+                }
+                i += 1;
+                if i >= 3 {
+                    bail!("Authentication (ssh) failed: too many attempts");
+                }
+                if !helpers::ask_confirmation_or(
+                    &format!("{prefix}passphrase-retry"),
+                    "Do you want to retry?",
+                    false,
+                )? {
+                    bail!("Authentication (ssh) failed by identity file: {err}")
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::{HashAlg, PrivateKey, PublicKey};
+use tracing::{info, warn};
+
+use super::AuthMethod;
+use crate::helpers::{command, file, prompt};
+
+/// Authorized keys of the login user, relative to its home directory (sftp).
+const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+
+impl super::Host {
+    /// Authorize the `--install-key` public key for the user and root after a password login,
+    /// so that the next connections (nixos-anywhere included) can authenticate by key.
+    pub(super) fn install_key(&mut self) -> Result<()> {
+        let Some(install_key) = self.args.install_key.clone() else {
+            return Ok(());
+        };
+        if !matches!(
+            self.args.auth,
+            Some(AuthMethod::Passwd | AuthMethod::KeyboardInteractive)
+        ) {
+            return Ok(());
+        }
+        let key = self.public_key(&install_key)?;
+        let fingerprint = key.fingerprint(HashAlg::Sha256);
+        let line = key.to_openssh()?;
+        let data = line
+            .split_whitespace()
+            .nth(1)
+            .ok_or_else(|| anyhow!("Encoding public key {fingerprint} failed"))?;
+        if self.dry_run {
+            info!(
+                "🔸 [dry-run] {fingerprint} would be authorized for {} and root",
+                self.user
+            );
+            return Ok(());
+        }
+
+        info!("🔑 Authorize {fingerprint} for {}", self.user);
+        self.authorize_user_key(&line, data)?;
+        if self.user != "root" {
+            info!("🔑 Authorize {fingerprint} for root");
+            let keys = "~root/.ssh/authorized_keys";
+            let cmd = format!(
+                "umask 077; mkdir -p ~root/.ssh && touch {keys} && (grep -qF {} {keys} || echo {} >> {keys})",
+                command::quote(data),
+                command::quote(&line),
+            );
+            // The key of the user is enough for nixos-anywhere, which uses sudo
+            if let Err(err) = self.run_privileged(&cmd) {
+                warn!("❗ Authorizing {fingerprint} for root failed: {err}");
+            }
+        }
+
+        // Spawned commands are given the key file, the session keeps its password
+        if install_key != "agent" && self.args.identity_file.is_none() {
+            let private_key = install_key.trim_end_matches(".pub");
+            if file::expand_home(private_key).exists() {
+                self.args.identity_file = Some(private_key.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Public key of `install_key`: an agent identity (`agent`), else a public or private key
+    /// file.
+    fn public_key(&self, install_key: &str) -> Result<PublicKey> {
+        if install_key != "agent" {
+            let path = file::expand_home(install_key);
+            return PublicKey::read_openssh_file(&path)
+                .or_else(|_| {
+                    PrivateKey::read_openssh_file(&path).map(|key| key.public_key().clone())
+                })
+                .context(format!("Reading public key {} failed", path.display()));
+        }
+
+        let mut agent = self.ssh.agent()?;
+        agent.connect().context("Connecting to ssh agent failed")?;
+        agent.list_identities()?;
+        let mut keys = Vec::new();
+        for identity in agent.identities()? {
+            let mut key = PublicKey::from_bytes(identity.blob())
+                .context("Parsing ssh agent identity failed")?;
+            key.set_comment(identity.comment());
+            keys.push(key);
+        }
+        let index = match keys.len() {
+            0 => bail!("No identity in ssh agent to authorize"),
+            1 => 0,
+            _ => prompt::select(
+                "install-key",
+                "Select the agent key to authorize?",
+                &keys
+                    .iter()
+                    .map(|key| format!("{} {}", key.fingerprint(HashAlg::Sha256), key.comment()))
+                    .collect::<Vec<String>>(),
+            )?,
+        };
+        keys.into_iter()
+            .nth(index)
+            .ok_or_else(|| anyhow!("Couldn't found selected agent key"))
+    }
+
+    /// Append the key to the authorized keys of the user, unless already there.
+    fn authorize_user_key(&self, line: &str, data: &str) -> Result<()> {
+        let sftp = self.sftp()?;
+        let path = Path::new(AUTHORIZED_KEYS);
+        let mut contents = match sftp.stat(path) {
+            Ok(_) => String::from_utf8(self.download_file(AUTHORIZED_KEYS)?)?,
+            Err(_) => {
+                let dir = PathBuf::from(".ssh");
+                if sftp.stat(&dir).is_err() {
+                    sftp.mkdir(&dir, 0o700)
+                        .context("Creating ~/.ssh (sftp) failed")?;
+                }
+                String::new()
+            }
+        };
+        if contents
+            .lines()
+            .any(|entry| entry.split_whitespace().any(|field| field == data))
+        {
+            info!("⏭️ Key already authorized for {}", self.user);
+            return Ok(());
+        }
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(line);
+        contents.push('\n');
+        self.upload_file(contents.as_bytes(), path, 0o600)
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+use ssh2::Channel;
+use tracing::{info, warn};
+
+use crate::helpers::command;
+
+/// Milliseconds to wait for the channel to close after a command timed out.
+const CLOSE_TIMEOUT_MS: u32 = 5_000;
+
+/// The session has been lost, as opposed to a command failing on the remote host.
+#[derive(Debug)]
+struct Disconnected(String);
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Connection (ssh) lost: {}", self.0)
+    }
+}
+
+impl Error for Disconnected {}
+
+fn lost(err: impl fmt::Display) -> anyhow::Error {
+    anyhow!(Disconnected(err.to_string()))
+}
+
+/// How a remote command is run.
+#[derive(Default)]
+pub struct ExecOptions<'a> {
+    /// Data written to the command, its stdin is closed afterwards
+    pub stdin: Option<&'a [u8]>,
+    /// Run the command in a pseudo-terminal (stderr is merged into stdout)
+    pub pty: bool,
+    /// Environment variables of the command
+    pub envs: &'a [(&'a str, &'a str)],
+    /// Log output lines as they come
+    pub live: bool,
+}
+
+/// Outcome of a remote command.
+pub struct Output {
+    pub status: i32,
+    /// Signal the command has been killed by
+    pub signal: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.status == 0 && self.signal.is_none()
+    }
+
+    /// The output of a successful command, an error with its stderr otherwise.
+    pub fn check(self) -> Result<Self> {
+        match (&self.signal, self.success()) {
+            (_, true) => Ok(self),
+            (Some(signal), _) => bail!(
+                "Command (ssh) killed by signal {signal} and stderr: \n{}",
+                self.stderr
+            ),
+            (None, _) => bail!(
+                "Command (ssh) fail with exit status ({}) and stderr: \n{}",
+                self.status,
+                self.stderr
+            ),
+        }
+    }
+}
+
+impl super::Host {
+    /// Run `cmd` on the remote host, reconnecting first when the session has been lost.
+    /// The commands run this way are read-only, running one again is harmless.
+    pub fn run_command(&mut self, cmd: &str) -> Result<String> {
+        let options = ExecOptions::default();
+        let output = match self.try_exec(cmd, &options) {
+            Err(err) if err.is::<Disconnected>() => {
+                warn!("❗ {err}, reconnecting");
+                self.establish(false)?;
+                self.try_exec(cmd, &options)?
+            }
+            result => result?,
+        };
+        Ok(output.check()?.stdout)
+    }
+
+    /// Run `cmd` with `options`, streaming its output. The session is opened again when it
+    /// has been lost before the command starts, never while it runs.
+    pub fn exec(&mut self, cmd: &str, options: &ExecOptions) -> Result<Output> {
+        let channel = match self.ssh.channel_session() {
+            Ok(channel) => channel,
+            Err(err) => {
+                warn!("❗ {}, reconnecting", Disconnected(err.to_string()));
+                self.establish(false)?;
+                self.ssh.channel_session().map_err(lost)?
+            }
+        };
+        self.run_channel(channel, cmd, options)
+    }
+
+    fn try_exec(&self, cmd: &str, options: &ExecOptions) -> Result<Output> {
+        let channel = self.ssh.channel_session().map_err(lost)?;
+        self.run_channel(channel, cmd, options)
+    }
+
+    fn run_channel(
+        &self,
+        mut channel: Channel,
+        cmd: &str,
+        options: &ExecOptions,
+    ) -> Result<Output> {
+        if options.pty {
+            channel.request_pty("xterm", None, None).map_err(lost)?;
+        }
+        let mut command_line = cmd.to_string();
+        for (name, value) in options.envs {
+            if channel.setenv(name, value).is_err() {
+                // Servers only accept the variables of their AcceptEnv
+                command_line = format!("export {name}={}; {command_line}", command::quote(value));
+            }
+        }
+        channel.exec(&command_line).map_err(lost)?;
+
+        // Both streams are read as they come, a full stderr window can't stall stdout
+        self.ssh.set_blocking(false);
+        let output = self.pump(&mut channel, cmd, options);
+        self.ssh.set_blocking(true);
+        let [stdout, stderr] = output?;
+
+        channel.wait_close().map_err(lost)?;
+        Ok(Output {
+            status: channel.exit_status().map_err(lost)?,
+            signal: channel.exit_signal().map_err(lost)?.exit_signal,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Feed stdin and read stdout and stderr until the command ends, sending keepalives
+    /// while it runs.
+    fn pump(&self, channel: &mut Channel, cmd: &str, options: &ExecOptions) -> Result<[String; 2]> {
+        let timeout = self.args.command_timeout.map(Duration::from_secs);
+        let started = Instant::now();
+        let mut stdin = options.stdin.unwrap_or_default();
+        let mut stdin_open = true;
+        let mut outputs = [Vec::new(), Vec::new()];
+        let mut logged = [0; 2];
+        let mut buf = [0; 32 * 1024];
+        loop {
+            let mut idle = true;
+            if stdin_open && !stdin.is_empty() {
+                match channel.write(stdin) {
+                    Ok(n) => {
+                        stdin = &stdin[n..];
+                        idle = false;
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(lost(err)),
+                }
+            } else if stdin_open {
+                match channel.send_eof().map_err(io::Error::from) {
+                    Ok(()) => stdin_open = false,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(lost(err)),
+                }
+            }
+
+            for (stream_id, output) in outputs.iter_mut().enumerate() {
+                match channel.stream(stream_id as i32).read(&mut buf) {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        output.extend_from_slice(&buf[..n]);
+                        idle = false;
+                        if options.live {
+                            logged[stream_id] += log_lines(stream_id, &output[logged[stream_id]..]);
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                    Err(err) => return Err(lost(err)),
+                }
+            }
+            // Checked on every pass, a command printing without end must time out as well
+            if let Some(timeout) = timeout
+                && started.elapsed() > timeout
+            {
+                // Close the channel so that the remote command is hung up instead of left
+                // running, bounded in case the connection is stalled as well
+                self.ssh.set_blocking(true);
+                self.ssh.set_timeout(CLOSE_TIMEOUT_MS);
+                let _ = channel.close();
+                self.ssh.set_timeout(0);
+                bail!(
+                    "Command (ssh) timed out after {}s: {cmd}",
+                    timeout.as_secs()
+                )
+            }
+            if !idle {
+                continue;
+            }
+            if channel.eof() {
+                break;
+            }
+            if let Err(err) = self.ssh.keepalive_send()
+                && io::Error::from(err).kind() != ErrorKind::WouldBlock
+            {
+                return Err(lost("keepalive failed"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        if options.live {
+            for (stream_id, output) in outputs.iter().enumerate() {
+                // The last line may have no line break
+                let rest = &output[logged[stream_id]..];
+                if !rest.is_empty() {
+                    log_lines(stream_id, &[rest, b"\n"].concat());
+                }
+            }
+        }
+        Ok(outputs.map(|output| String::from_utf8_lossy(&output).to_string()))
+    }
+}
+
+/// Log the complete lines of `output`, returns the length logged.
+fn log_lines(stream_id: usize, output: &[u8]) -> usize {
+    let Some(end) = output.iter().rposition(|byte| *byte == b'\n') else {
+        return 0;
+    };
+    for line in String::from_utf8_lossy(&output[..end]).lines() {
+        match stream_id {
+            0 => info!("🔸 {line}"),
+            _ => warn!("❗ {line}"),
+        }
+    }
+    end + 1
+}
@@ -0,0 +1,248 @@
+use std::{
+    env,
+    io::{ErrorKind, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::PublicKey;
+use ssh2::{Channel, Session};
+use tracing::{info, warn};
+
+use super::{
+    address,
+    auth::{self, AuthMethod, Credentials},
+    ssh,
+};
+use crate::{
+    cli::RemoteArgs,
+    helpers::{prompt, ssh_config::SshConfig},
+    local,
+};
+
+/// Jump host of a ProxyJump chain (`[user@]host[:port]`).
+struct Jump {
+    user: Option<String>,
+    destination: String,
+    port: Option<u16>,
+}
+
+/// Authentication of a jump host, kept to reconnect without asking again.
+#[derive(Default)]
+pub struct Hop {
+    args: RemoteArgs,
+    credentials: Credentials,
+}
+
+fn parse_chain(chain: &str) -> Result<Vec<Jump>> {
+    chain
+        .split(',')
+        .map(|jump| {
+            let jump = jump.trim();
+            let jump = jump.strip_prefix("ssh://").unwrap_or(jump);
+            let (user, host) = match jump.rsplit_once('@') {
+                Some((user, host)) => (Some(user.to_string()), host),
+                None => (None, jump),
+            };
+            let (destination, port) = match host.strip_prefix('[') {
+                Some(bracketed) => match bracketed.split_once(']') {
+                    Some((destination, rest)) => (destination, rest.strip_prefix(':')),
+                    None => bail!("Invalid jump host {jump}"),
+                },
+                None => match host.split_once(':') {
+                    Some((destination, port)) if !port.contains(':') => (destination, Some(port)),
+                    _ => (host, None),
+                },
+            };
+            if destination.is_empty() {
+                bail!("Invalid jump host {jump}")
+            }
+            let port = port
+                .map(|port| port.parse::<u16>())
+                .transpose()
+                .map_err(|_| anyhow!("Invalid port of jump host {jump}"))?;
+            Ok(Jump {
+                user,
+                destination: destination.to_string(),
+                port,
+            })
+        })
+        .collect()
+}
+
+/// Stream to `hostname:port` tunneled through every jump host of `chain`, in order.
+pub fn open(
+    chain: &str,
+    hostname: &str,
+    port: u16,
+    args: &RemoteArgs,
+    hops: &mut Vec<Hop>,
+    known_hosts: &local::ssh::Info,
+) -> Result<UnixStream> {
+    let jumps = parse_chain(chain)?;
+    if hops.len() != jumps.len() {
+        *hops = jumps.iter().map(|_| Hop::default()).collect();
+    }
+    let mut outer = None;
+    for (jump, hop) in jumps.iter().zip(hops.iter_mut()) {
+        outer = Some(connect_hop(jump, hop, outer.take(), args, known_hosts)?);
+    }
+    let outer = outer.ok_or_else(|| anyhow!("Empty jump hosts chain"))?;
+    forward(outer, hostname, port)
+}
+
+/// Authenticated session on a jump host, reached through `outer` when given.
+fn connect_hop(
+    jump: &Jump,
+    hop: &mut Hop,
+    outer: Option<Session>,
+    args: &RemoteArgs,
+    known_hosts: &local::ssh::Info,
+) -> Result<Session> {
+    let ssh_config = SshConfig::load()?.resolve(&jump.destination);
+    let hostname = match &ssh_config.hostname {
+        Some(hostname) => address::split_destination(hostname)?.0,
+        None => jump.destination.to_string(),
+    };
+    let port = jump.port.or(ssh_config.port).unwrap_or(22);
+    let user = match jump.user.as_ref().or(ssh_config.user.as_ref()) {
+        Some(user) => user.to_string(),
+        None => match env::var("USER") {
+            Ok(user) => user,
+            Err(_) => prompt::input("jump-user", "Enter ssh user of the jump host:", None)?,
+        },
+    };
+    if hop.args.identity_file.is_none() {
+        hop.args.identity_file = ssh_config
+            .identity_files
+            .iter()
+            .find(|path| Path::new(path).exists())
+            .cloned();
+    }
+    let addr = address::format(&hostname, port);
+    info!("🔑 Try to connect (via ssh) to jump host {user}@{addr}");
+
+    let timeout = ssh::connect_timeout(args);
+    let mut sess = Session::new().context("Session (ssh) creation failed")?;
+    match outer {
+        Some(outer) => sess.set_tcp_stream(forward(outer, &hostname, port)?),
+        None => sess.set_tcp_stream(address::connect(&hostname, port, timeout)?),
+    }
+    sess.set_timeout(timeout.as_millis() as u32);
+    sess.handshake()
+        .context(format!("Handshake (ssh) with jump host {addr} failed"))?;
+    // Only the connection is bounded, prompts answered during authentication take their time
+    sess.set_timeout(0);
+    let (pk_bytes, _) = sess
+        .host_key()
+        .ok_or(anyhow!("No public key (ssh) found"))?;
+    let pk = PublicKey::from_bytes(pk_bytes)
+        .context("Jump host public key parsing from bytes failed")?
+        .to_openssh()
+        .context("Jump host public key conversion to OpenSSH format failed")?;
+    known_hosts.verify_host_key(&hostname, &port.to_string(), &pk, false, None)?;
+
+    // Jump hosts usually trust the agent keys, only ask when they don't
+    if hop.args.auth.is_none() && sess.userauth_agent(&user).is_ok() && sess.authenticated() {
+        hop.args.auth = Some(AuthMethod::Agent);
+    } else {
+        auth::authenticate(&sess, &user, &mut hop.args, &mut hop.credentials, "jump-")?;
+    }
+    ssh::set_keepalive(&sess, args);
+    info!("🔸 Jump host connected (via ssh) to {addr}");
+    Ok(sess)
+}
+
+/// Open a `direct-tcpip` channel to `host:port` on `outer` and pump it through a socket pair.
+fn forward(outer: Session, host: &str, port: u16) -> Result<UnixStream> {
+    let channel = outer
+        .channel_direct_tcpip(host, port, None)
+        .context(format!(
+            "Forwarding (ssh) to {} failed",
+            address::format(host, port)
+        ))?;
+    let (stream, tunnel) = UnixStream::pair().context("Socket pair creation failed")?;
+    tunnel.set_nonblocking(true)?;
+    let target = address::format(host, port);
+    thread::spawn(move || {
+        if let Err(err) = pump(&outer, channel, tunnel) {
+            warn!("❗ Tunnel (ssh) to {target} closed: {err}");
+        }
+    });
+    Ok(stream)
+}
+
+/// Copy both ways between the channel and the socket until one of them is closed.
+fn pump(outer: &Session, mut channel: Channel, mut tunnel: UnixStream) -> std::io::Result<()> {
+    // The session only serves this channel, polling it doesn't stall anything else
+    outer.set_blocking(false);
+    let mut buf = [0; 32 * 1024];
+    let mut up = Vec::new();
+    let mut down = Vec::new();
+    let mut tunnel_eof = false;
+    loop {
+        let mut idle = true;
+
+        if up.is_empty() && !tunnel_eof {
+            match tunnel.read(&mut buf) {
+                Ok(0) => {
+                    tunnel_eof = true;
+                    idle = false;
+                }
+                Ok(n) => {
+                    up.extend_from_slice(&buf[..n]);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !up.is_empty() {
+            match channel.write(&up) {
+                Ok(n) => {
+                    up.drain(..n);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if tunnel_eof && up.is_empty() {
+            // The inner session has been dropped
+            let _ = channel.send_eof();
+            return Ok(());
+        }
+
+        if down.is_empty() {
+            match channel.read(&mut buf) {
+                Ok(0) if channel.eof() => return Ok(()),
+                Ok(0) => {}
+                Ok(n) => {
+                    down.extend_from_slice(&buf[..n]);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if !down.is_empty() {
+            match tunnel.write(&down) {
+                Ok(n) => {
+                    down.drain(..n);
+                    idle = false;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if idle {
+            // Only sent once the keepalive interval has elapsed
+            let _ = outer.keepalive_send();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -1,92 +1,252 @@
-use anyhow::{Result, anyhow};
-use dialoguer::{Input, Select, theme::ColorfulTheme};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh_key::PublicKey;
 use ssh2::Session;
 use tracing::{info, warn};
 
 use crate::{
-    helpers::{self, disk::DiskDevices},
+    cli::RemoteArgs,
+    helpers::{self, disk::DiskDevices, prompt},
     local,
 };
 
+mod address;
+mod auth;
+mod authorized_keys;
 mod config;
+mod exec;
+mod jump;
+mod privilege;
+mod sftp;
 mod ssh;
 
+pub use address::split_destination;
+pub use auth::AuthMethod;
+use auth::Credentials;
+use exec::ExecOptions;
+use jump::Hop;
+pub use privilege::Privilege;
+
+/// Host key sops-nix derives the age key of the host from.
+const HOST_ED25519_PK: &str = "/etc/ssh/ssh_host_ed25519_key.pub";
+
 pub struct Host {
+    /// Destination as given, possibly an ssh config alias
     pub destination: String,
+    /// Address the destination resolves to
+    pub hostname: String,
     pub user: String,
     pub port: String,
+    /// ProxyJump chain the host is reached through
+    pub jump: Option<String>,
     ssh: Session,
+    credentials: Credentials,
+    hops: Vec<Hop>,
+    known_hosts: local::ssh::Info,
     pub ssh_pk: String,
     pub config: config::Config,
+    pub args: RemoteArgs,
+    cli_args: RemoteArgs,
+    dry_run: bool,
 }
 
 impl Host {
-    pub fn new(local: &local::Host) -> Result<Self> {
-        let destination = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Enter ssh destination:")
-            .default("127.0.0.1".to_string())
-            .allow_empty(false)
-            .show_default(true)
-            .interact_text()?;
-        let (ssh, ssh_pk, user, port) = Self::connect(&destination, local)?;
-        Ok(Self {
-            user,
+    pub fn new(local: &local::Host, cli_args: &RemoteArgs) -> Result<Self> {
+        Self::open(local, cli_args, false)
+    }
+
+    /// Connect to a host installed by an earlier nixos-anywhere run, a pinned fingerprint was
+    /// the one of its installer.
+    pub fn new_installed(local: &local::Host, cli_args: &RemoteArgs) -> Result<Self> {
+        Self::open(local, cli_args, true)
+    }
+
+    fn open(local: &local::Host, cli_args: &RemoteArgs, reinstall: bool) -> Result<Self> {
+        let mut args = Self::resolve_args(local, cli_args);
+        let destination = match &args.destination {
+            Some(destination) => destination.to_string(),
+            None => prompt::input("destination", "Enter ssh destination:", Some("127.0.0.1"))?,
+        };
+        let (destination, port) = address::split_destination(&destination)?;
+        args.port = args.port.or(port);
+        args.destination = Some(destination.to_string());
+        let mut credentials = Credentials::default();
+        let mut hops = Vec::new();
+        let connection = Self::connect(
+            &destination,
+            &mut args,
+            &mut credentials,
+            &mut hops,
+            &local.ssh,
+            reinstall,
+        )?;
+        let mut host = Self {
+            user: connection.user,
             destination,
-            port,
-            ssh,
-            ssh_pk,
+            hostname: connection.hostname,
+            port: connection.port,
+            jump: connection.jump,
+            ssh: connection.sess,
+            credentials,
+            hops,
+            known_hosts: local.ssh.clone(),
+            ssh_pk: connection.pk,
             config: config::Config::default(),
-        })
+            args,
+            cli_args: cli_args.clone(),
+            dry_run: local.dry_run,
+        };
+        host.install_key()?;
+        Ok(host)
+    }
+
+    fn resolve_args(local: &local::Host, cli_args: &RemoteArgs) -> RemoteArgs {
+        let mut args = cli_args.clone();
+        if let Ok(repo) = local.get_repo()
+            && let Some(entry) = repo.get_host_entry()
+        {
+            info!("🔸 Use inventory entry of {}", repo.host);
+            args.fill(&entry.get_remote_args());
+        }
+        args
     }
 
     pub fn get_hardware_config(&mut self) -> Result<bool> {
-        if !helpers::ask_confirmation("Do you want to get hardware configuration?")? {
+        if !helpers::ask_confirmation(
+            "hardware-config",
+            "Do you want to get hardware configuration?",
+        )? {
             warn!("❗ Skipping hardware-configuration part");
             return Ok(false);
         }
+        self.fetch_hardware_config()?;
+        Ok(true)
+    }
 
+    pub fn fetch_hardware_config(&mut self) -> Result<()> {
         info!("🔧 Get hardware configuration");
-        self.run_command("nixos-generate-config --no-filesystems --root /tmp")?;
+        let command = "nixos-generate-config --no-filesystems --root /tmp";
+        if self.dry_run {
+            info!("🔸 [dry-run] {command} would be run on remote host");
+            self.config.hardware_file = Some(
+                self.run_command("nixos-generate-config --no-filesystems --show-hardware-config")?
+                    .into_bytes(),
+            );
+            return Ok(());
+        }
+        let options = ExecOptions {
+            live: true,
+            ..Default::default()
+        };
+        self.exec(command, &options)?.check()?;
         self.config.hardware_file =
             Some(self.download_file("/tmp/etc/nixos/hardware-configuration.nix")?);
-        Ok(true)
+        Ok(())
     }
 
     pub fn get_disk_device(&mut self) -> Result<bool> {
-        if !helpers::ask_confirmation("Do you want to select a disk device?")? {
+        if self.args.disk.is_none()
+            && !helpers::ask_confirmation("disk-device", "Do you want to select a disk device?")?
+        {
             warn!("❗ Skipping disk device selection");
             return Ok(false);
         }
+        self.select_disk_device()?;
+        Ok(true)
+    }
 
+    pub fn select_disk_device(&mut self) -> Result<()> {
         let disk_devices = serde_json::from_str::<DiskDevices>(
             &self.run_command("lsblk -d -J -o NAME,SIZE,MODEL,MOUNTPOINT")?,
         )?;
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select a target block device?")
-            .items(
-                &disk_devices
+        let disk_device = match &self.args.disk {
+            Some(disk) => {
+                let name = disk.trim_start_matches("/dev/");
+                disk_devices
                     .blockdevices
                     .iter()
-                    .map(|disk_device| disk_device.get_info())
-                    .collect::<Vec<String>>(),
-            )
-            .interact()?;
-        let disk_device = disk_devices
-            .blockdevices
-            .get(selection)
-            .ok_or_else(|| anyhow!("Couldn't found selected disk found"))?;
+                    .find(|disk_device| disk_device.name == name)
+                    .ok_or_else(|| anyhow!("Disk device {disk} not found on remote host"))?
+            }
+            None => {
+                let selection = prompt::select(
+                    "disk",
+                    "Select a target block device?",
+                    &disk_devices
+                        .blockdevices
+                        .iter()
+                        .map(|disk_device| disk_device.get_info())
+                        .collect::<Vec<String>>(),
+                )?;
+                disk_devices
+                    .blockdevices
+                    .get(selection)
+                    .ok_or_else(|| anyhow!("Couldn't found selected disk found"))?
+            }
+        };
+        info!("🔸 Disk device selected: {}", disk_device.get_info());
         self.config.disk_device = Some(disk_device.to_owned());
-        Ok(true)
+        Ok(())
     }
 
     pub fn get_age_key(&mut self) -> Result<bool> {
-        if !helpers::ask_confirmation("Do you want to get age key?")? {
+        if !helpers::ask_confirmation("age-key", "Do you want to get age key?")? {
             warn!("❗ Skipping age key part");
             return Ok(false);
         }
+        self.fetch_age_key()?;
+        Ok(true)
+    }
 
+    pub fn fetch_age_key(&mut self) -> Result<()> {
         info!("🔑 Get age key");
-        self.config.age_pk = Some(ssh_to_age::convert::ssh_public_key_to_age(&self.ssh_pk)?);
-        Ok(true)
+        let host_pk = self
+            .run_command(&format!("cat {HOST_ED25519_PK}"))
+            .context(format!("Reading {HOST_ED25519_PK} failed"))?;
+        let host_pk = PublicKey::from_openssh(host_pk.trim())
+            .context(format!("Parsing {HOST_ED25519_PK} failed"))?;
+        let handshake_pk = PublicKey::from_openssh(&self.ssh_pk)
+            .context("Parsing handshake public key (ssh) failed")?;
+        if host_pk.key_data() != handshake_pk.key_data() {
+            bail!("{HOST_ED25519_PK} doesn't match the host key offered during the handshake (ssh)")
+        }
+        self.config.age_pk = Some(ssh_to_age::convert::ssh_public_key_to_age(
+            &handshake_pk.to_openssh()?,
+        )?);
+        Ok(())
+    }
+
+    /// Download every `/etc/ssh/ssh_host_*` file, keyed by file name.
+    pub fn fetch_host_keys(&mut self) -> Result<BTreeMap<String, String>> {
+        info!("🔑 Get ssh host keys");
+        let mut keys = BTreeMap::new();
+        // Private host keys are only readable by root, others read them with privileges
+        if self.user == "root" {
+            let dir = tempfile::tempdir().context("Failed to create temp directory")?;
+            self.download(Path::new("/etc/ssh"), dir.path(), false)?;
+            for entry in fs::read_dir(dir.path())? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with("ssh_host_") && entry.path().is_file() {
+                    keys.insert(name, fs::read_to_string(entry.path())?);
+                }
+            }
+        } else {
+            for name in self
+                .run_command("ls /etc/ssh")?
+                .lines()
+                .filter(|name| name.starts_with("ssh_host_"))
+            {
+                let contents = self
+                    .run_privileged(&format!("cat /etc/ssh/{name}"))
+                    .context(format!("Reading /etc/ssh/{name} failed"))?;
+                keys.insert(name.to_string(), contents);
+            }
+        }
+        if keys.is_empty() {
+            bail!("No ssh host key found in /etc/ssh on remote host")
+        }
+        Ok(keys)
     }
 }
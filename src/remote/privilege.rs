@@ -0,0 +1,119 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::exec::ExecOptions;
+use crate::helpers::{command, prompt};
+
+/// How commands needing root are run on the remote host.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Privilege {
+    /// The user is root already
+    None,
+    /// sudo without password (NOPASSWD)
+    Sudo,
+    /// sudo with the password fed over stdin
+    SudoPassword,
+    Doas,
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Privilege::None => "none",
+            Privilege::Sudo => "sudo",
+            Privilege::SudoPassword => "sudo-password",
+            Privilege::Doas => "doas",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Privilege {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Privilege::None),
+            "sudo" => Ok(Privilege::Sudo),
+            "sudo-password" => Ok(Privilege::SudoPassword),
+            "doas" => Ok(Privilege::Doas),
+            _ => Err(format!("Invalid privilege mode: {}", s)),
+        }
+    }
+}
+
+impl super::Host {
+    /// Run `cmd` as root with the privilege mode of the host.
+    pub fn run_privileged(&mut self, cmd: &str) -> Result<String> {
+        let quoted = command::quote(cmd);
+        match self.privilege()? {
+            Privilege::None => self.run_command(cmd),
+            Privilege::Sudo => self.run_command(&format!("sudo -n sh -c {quoted}")),
+            Privilege::Doas => self.run_command(&format!("doas -n sh -c {quoted}")),
+            Privilege::SudoPassword => self.run_sudo_password(&quoted),
+        }
+    }
+
+    /// Privilege mode given for the host, else the first one working without password.
+    pub fn privilege(&mut self) -> Result<Privilege> {
+        if let Some(privilege) = &self.args.privilege {
+            return Ok(privilege.clone());
+        }
+        let options = ExecOptions::default();
+        let privilege = if self.user == "root" {
+            Privilege::None
+        } else if self.exec("sudo -n true", &options)?.success() {
+            Privilege::Sudo
+        } else if self.exec("doas -n true", &options)?.success() {
+            Privilege::Doas
+        } else {
+            Privilege::SudoPassword
+        };
+        info!("🔸 Privileged commands run with {privilege}");
+        self.args.privilege = Some(privilege.clone());
+        Ok(privilege)
+    }
+
+    /// sudo reading the password from stdin, kept for the next privileged commands.
+    fn run_sudo_password(&mut self, quoted: &str) -> Result<String> {
+        let cmd = format!("sudo -S -p '' sh -c {quoted}");
+        let mut i = 0;
+        loop {
+            let password = match (
+                self.credentials.sudo_password.take(),
+                &self.credentials.password,
+            ) {
+                (Some(password), _) => password,
+                // The login password is usually the sudo one
+                (None, Some(password)) if i == 0 => password.clone(),
+                _ => prompt::password(
+                    "sudo-password",
+                    &format!("Enter sudo password of {}:", self.user),
+                )?,
+            };
+            let stdin = format!("{password}\n");
+            let options = ExecOptions {
+                stdin: Some(stdin.as_bytes()),
+                ..Default::default()
+            };
+            let output = self.exec(&cmd, &options)?;
+            if !output.success()
+                && (output.stderr.contains("incorrect password")
+                    || output.stderr.contains("Sorry, try again"))
+            {
+                i += 1;
+                if i >= 3 {
+                    bail!("sudo failed: too many attempts");
+                }
+                warn!("❗ Wrong sudo password");
+                continue;
+            }
+            self.credentials.sudo_password = Some(password);
+            return Ok(output.check()?.stdout);
+        }
+    }
+}
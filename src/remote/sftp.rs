@@ -0,0 +1,268 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags, Sftp};
+use tracing::{info, warn};
+
+use crate::helpers::extra_files::ExtraFiles;
+
+/// Files above this size report their transfer progress.
+const PROGRESS_MIN_SIZE: u64 = 1024 * 1024;
+
+/// Transfer progress, logged every quarter.
+struct Progress<'a> {
+    label: &'a str,
+    total: u64,
+    done: u64,
+    reported: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(label: &'a str, total: u64) -> Self {
+        Self {
+            label,
+            total,
+            done: 0,
+            reported: 0,
+        }
+    }
+
+    fn add(&mut self, n: usize) {
+        self.done += n as u64;
+        if self.total < PROGRESS_MIN_SIZE {
+            return;
+        }
+        let percent = (self.done * 100 / self.total).min(100);
+        if percent >= self.reported + 25 {
+            self.reported = percent - percent % 25;
+            info!("🔸 {}: {percent}%", self.label);
+        }
+    }
+}
+
+fn copy(reader: &mut impl Read, writer: &mut impl Write, progress: &mut Progress) -> Result<()> {
+    let mut buf = [0; 32 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buf[..n])?;
+        progress.add(n);
+    }
+}
+
+impl super::Host {
+    pub(super) fn sftp(&self) -> Result<Sftp> {
+        self.ssh
+            .sftp()
+            .context("SFTP subsystem (ssh) couldn't be started")
+    }
+
+    /// Contents of a remote file.
+    pub fn download_file(&self, remote_path: &str) -> Result<Vec<u8>> {
+        let sftp = self.sftp()?;
+        let mut file = sftp
+            .open(Path::new(remote_path))
+            .context(format!("Opening {remote_path} (sftp) failed"))?;
+        let size = file.stat()?.size.unwrap_or_default();
+        let mut contents = Vec::new();
+        copy(
+            &mut file,
+            &mut contents,
+            &mut Progress::new(remote_path, size),
+        )?;
+        Ok(contents)
+    }
+
+    /// Download a remote file or directory (recursively), keeping modes, and owners when
+    /// `keep_owners` is set.
+    pub fn download(&self, remote: &Path, local: &Path, keep_owners: bool) -> Result<()> {
+        info!("🔸 Download {} to {}", remote.display(), local.display());
+        Self::download_path(&self.sftp()?, remote, local, keep_owners)
+    }
+
+    fn download_path(sftp: &Sftp, remote: &Path, local: &Path, keep_owners: bool) -> Result<()> {
+        let stat = sftp
+            .stat(remote)
+            .context(format!("Reading {} (sftp) failed", remote.display()))?;
+        if stat.is_dir() {
+            fs::create_dir_all(local).context(format!("Creating {} failed", local.display()))?;
+            for (path, _) in sftp.readdir(remote)? {
+                let Some(name) = path.file_name() else {
+                    continue;
+                };
+                Self::download_path(sftp, &path, &local.join(name), keep_owners)?;
+            }
+        } else {
+            let mut file = sftp.open(remote)?;
+            let mut local_file =
+                fs::File::create(local).context(format!("Creating {} failed", local.display()))?;
+            let label = remote.display().to_string();
+            copy(
+                &mut file,
+                &mut local_file,
+                &mut Progress::new(&label, stat.size.unwrap_or_default()),
+            )?;
+        }
+
+        if let Some(perm) = stat.perm {
+            fs::set_permissions(local, fs::Permissions::from_mode(perm & 0o7777))?;
+        }
+        // Remote ids mean other users locally, only kept when asked
+        if keep_owners {
+            std::os::unix::fs::chown(local, stat.uid, stat.gid)
+                .context(format!("Changing owner of {} failed", local.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Write `contents` into a remote file atomically: a temporary file next to it is
+    /// renamed over it once complete.
+    pub fn upload_file(&self, contents: &[u8], remote: &Path, mode: u32) -> Result<()> {
+        if self.dry_run {
+            info!("🔸 [dry-run] {} would be uploaded", remote.display());
+            return Ok(());
+        }
+        info!("🔸 Upload {}", remote.display());
+        let stat = FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(mode),
+            atime: None,
+            mtime: None,
+        };
+        self.write_atomic(
+            &self.sftp()?,
+            &mut &contents[..],
+            contents.len() as u64,
+            remote,
+            stat,
+        )
+    }
+
+    /// Upload a local file or directory (recursively), keeping modes, and owners when
+    /// `keep_owners` is set and connected as root. Existing remote directories are left as
+    /// they are.
+    pub fn upload(&self, local: &Path, remote: &Path, keep_owners: bool) -> Result<()> {
+        if self.dry_run {
+            info!(
+                "🔸 [dry-run] {} would be uploaded to {}",
+                local.display(),
+                remote.display()
+            );
+            return Ok(());
+        }
+        info!("🔸 Upload {} to {}", local.display(), remote.display());
+        self.upload_path(&self.sftp()?, local, remote, keep_owners)
+    }
+
+    fn upload_path(
+        &self,
+        sftp: &Sftp,
+        local: &Path,
+        remote: &Path,
+        keep_owners: bool,
+    ) -> Result<()> {
+        let metadata =
+            fs::symlink_metadata(local).context(format!("Reading {} failed", local.display()))?;
+        let owned = keep_owners && self.user == "root";
+        let stat = FileStat {
+            size: None,
+            uid: owned.then_some(metadata.uid()),
+            gid: owned.then_some(metadata.gid()),
+            perm: Some(metadata.mode() & 0o7777),
+            atime: None,
+            mtime: None,
+        };
+
+        if metadata.is_symlink() {
+            warn!("❗ Symlink {} skipped", local.display());
+        } else if metadata.is_dir() {
+            let created = sftp.stat(remote).is_err();
+            if created {
+                sftp.mkdir(remote, 0o755)
+                    .context(format!("Creating {} (sftp) failed", remote.display()))?;
+            }
+            let mut entries = fs::read_dir(local)?.collect::<Result<Vec<_>, _>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                self.upload_path(
+                    sftp,
+                    &entry.path(),
+                    &remote.join(entry.file_name()),
+                    keep_owners,
+                )?;
+            }
+            if created {
+                sftp.setstat(remote, stat).context(format!(
+                    "Setting mode of {} (sftp) failed",
+                    remote.display()
+                ))?;
+            }
+        } else {
+            let mut file =
+                fs::File::open(local).context(format!("Opening {} failed", local.display()))?;
+            self.write_atomic(sftp, &mut file, metadata.len(), remote, stat)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the extra files over the root of the running host, as nixos-anywhere
+    /// `--extra-files` does on install. Files end up owned by root.
+    pub fn push_extra_files(&self, extra_files: &ExtraFiles) -> Result<()> {
+        if self.user != "root" {
+            bail!("Extra files are pushed over SFTP, which needs to be logged in as root")
+        }
+        self.upload(extra_files.path(), Path::new("/"), false)
+    }
+
+    fn write_atomic(
+        &self,
+        sftp: &Sftp,
+        reader: &mut impl Read,
+        size: u64,
+        remote: &Path,
+        stat: FileStat,
+    ) -> Result<()> {
+        let name = remote
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid remote path {}", remote.display()))?;
+        let tmp = remote.with_file_name(format!(".{}.nix-bootstrap", name.to_string_lossy()));
+        let mut file = sftp
+            .open_mode(
+                &tmp,
+                OpenFlags::WRITE | OpenFlags::TRUNCATE,
+                0o600,
+                OpenType::File,
+            )
+            .context(format!("Creating {} (sftp) failed", tmp.display()))?;
+        let label = remote.display().to_string();
+        copy(reader, &mut file, &mut Progress::new(&label, size))?;
+        // Not every server supports fsync, the rename still happens after the writes
+        let _ = file.fsync();
+        file.close()?;
+        sftp.setstat(&tmp, stat)
+            .context(format!("Setting mode of {} (sftp) failed", tmp.display()))?;
+
+        let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        if let Err(err) = sftp.rename(&tmp, remote, Some(flags)) {
+            // SFTP v3 servers (OpenSSH) refuse to rename over an existing file
+            if sftp.stat(remote).is_err() {
+                let _ = sftp.unlink(&tmp);
+                return Err(err).context(format!("Renaming {} (sftp) failed", tmp.display()));
+            }
+            sftp.unlink(remote)
+                .context(format!("Replacing {} (sftp) failed", remote.display()))?;
+            sftp.rename(&tmp, remote, None)
+                .context(format!("Renaming {} (sftp) failed", tmp.display()))?;
+        }
+        Ok(())
+    }
+}
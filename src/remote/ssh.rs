@@ -1,190 +1,188 @@
-use std::{
-    fmt,
-    io::Read,
-    net::{TcpStream, ToSocketAddrs},
-    path::Path,
-    str::FromStr,
-};
+use std::{path::Path, time::Duration};
 
 use anyhow::{Context, Result, anyhow, bail};
-use dialoguer::{Input, Password, Select, theme::ColorfulTheme};
 use ssh_key::PublicKey;
-use ssh2::Session;
+use ssh2::{HostKeyType, MethodType, Session};
 use tracing::info;
 
-use crate::{helpers, local};
-
-#[derive(Debug)]
-enum AuthMethod {
-    Agent,
-    Passwd,
-}
+use super::{
+    address,
+    auth::{self, Credentials},
+    jump::{self, Hop},
+};
+use crate::{
+    cli::RemoteArgs,
+    helpers::{prompt, ssh_config::SshConfig},
+    local,
+};
 
-impl fmt::Display for AuthMethod {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s = match self {
-            AuthMethod::Agent => "agent",
-            AuthMethod::Passwd => "password",
-        };
-        write!(f, "{}", s)
-    }
+/// Seconds to wait for the connection and handshake when not set.
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+/// Seconds between keepalives when not set.
+const DEFAULT_KEEPALIVE: u32 = 15;
+
+/// Authenticated session with the host it has been resolved to.
+pub struct Connection {
+    pub sess: Session,
+    pub pk: String,
+    pub hostname: String,
+    pub user: String,
+    pub port: String,
+    /// ProxyJump chain the host is reached through
+    pub jump: Option<String>,
 }
 
-impl FromStr for AuthMethod {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "agent" => Ok(AuthMethod::Agent),
-            "password" => Ok(AuthMethod::Passwd),
-            _ => Err(format!("Invalid authentication method: {}", s)),
+impl super::Host {
+    /// Connect again, after a `reinstall` a changed host key is expected.
+    pub fn reconnect(&mut self, local: &local::Host, reinstall: bool) -> Result<()> {
+        let mut args = Self::resolve_args(local, &self.cli_args);
+        // Keep the authentication chosen for this host
+        if args.auth.is_none() {
+            args.auth = self.args.auth.take();
+            args.identity_file = args.identity_file.or(self.args.identity_file.take());
+        }
+        if let Some(destination) = &args.destination {
+            let (destination, port) = address::split_destination(destination)?;
+            args.port = args.port.or(port);
+            args.destination = Some(destination.clone());
+            self.destination = destination;
         }
+        self.args = args;
+        self.establish(reinstall)
     }
-}
 
-impl super::Host {
-    pub fn reconnect(&mut self, local: &local::Host) -> Result<()> {
-        let (ssh, ssh_pk, user, port) = Self::connect(&self.destination, local)?;
-        self.ssh = ssh;
-        self.port = port;
-        self.ssh_pk = ssh_pk;
-        self.user = user;
+    /// Open the session again with the arguments and credentials already given.
+    pub(super) fn establish(&mut self, reinstall: bool) -> Result<()> {
+        let connection = Self::connect(
+            &self.destination,
+            &mut self.args,
+            &mut self.credentials,
+            &mut self.hops,
+            &self.known_hosts,
+            reinstall,
+        )?;
+        self.ssh = connection.sess;
+        self.hostname = connection.hostname;
+        self.port = connection.port;
+        self.ssh_pk = connection.pk;
+        self.user = connection.user;
+        self.jump = connection.jump;
         Ok(())
     }
 
     pub fn connect(
         destination: &str,
-        local: &local::Host,
-    ) -> Result<(Session, String, String, String)> {
+        args: &mut RemoteArgs,
+        credentials: &mut Credentials,
+        hops: &mut Vec<Hop>,
+        known_hosts: &local::ssh::Info,
+        reinstall: bool,
+    ) -> Result<Connection> {
         info!("🔑 Try to connect (via ssh) to remote host");
-        let port = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Enter ssh port (1-65535):")
-            .default("22".to_string())
-            .allow_empty(false)
-            .show_default(true)
-            .validate_with(|input: &String| -> Result<(), &str> {
-                input
-                    .parse::<u16>()
-                    .map_err(|_| "Please enter a valid number between 1 and 65535")
-                    .and_then(|n| {
-                        if (1..=65535).contains(&n) {
-                            Ok(())
-                        } else {
-                            Err("Port must be between 1 and 65535")
-                        }
-                    })
-            })
-            .interact_text()?;
-        let addr = format!("{destination}:{port}");
-        let socket_addr = addr
-            .to_socket_addrs()?
-            .next()
-            .ok_or_else(|| anyhow!("Could not resolve address: {addr}"))?;
-        let tcp =
-            TcpStream::connect(socket_addr).context(anyhow!("Failed to connect to {addr}"))?;
+        let ssh_config = SshConfig::load()?.resolve(destination);
+        let hostname = match &ssh_config.hostname {
+            Some(hostname) => address::split_destination(hostname)?.0,
+            None => destination.to_string(),
+        };
+        if hostname != destination {
+            info!("🔸 {destination} is {hostname} in ssh config");
+        }
+        let jump = args.jump.clone().or(ssh_config.proxy_jump.clone());
+        if args.identity_file.is_none() {
+            args.identity_file = ssh_config
+                .identity_files
+                .iter()
+                .find(|path| Path::new(path).exists())
+                .cloned();
+        }
+
+        let port = match args.port.or(ssh_config.port) {
+            Some(port) => port.to_string(),
+            None => prompt::input_validated(
+                "port",
+                "Enter ssh port (1-65535):",
+                Some("22"),
+                &|input| {
+                    input
+                        .parse::<u16>()
+                        .map_err(|_| "Please enter a valid number between 1 and 65535".to_string())
+                        .and_then(|n| {
+                            if (1..=65535).contains(&n) {
+                                Ok(())
+                            } else {
+                                Err("Port must be between 1 and 65535".to_string())
+                            }
+                        })
+                },
+            )?,
+        };
+        args.port = Some(port.parse()?);
+        let addr = address::format(&hostname, port.parse()?);
+        let timeout = connect_timeout(args);
         let mut sess = Session::new().context("Session (ssh) creation failed")?;
-        sess.set_tcp_stream(tcp);
-        sess.handshake().context("Handshake (ssh) failed")?;
-        let (pk_bytes, _) = sess
+        // The age key of the host is derived from its ed25519 host key, the one sops-nix uses
+        sess.method_pref(MethodType::HostKey, "ssh-ed25519")
+            .context("Host key algorithm (ssh) preference failed")?;
+        match &jump {
+            Some(chain) => {
+                info!("🔸 Reach {addr} through {chain}");
+                let stream = jump::open(chain, &hostname, port.parse()?, args, hops, known_hosts)?;
+                sess.set_tcp_stream(stream);
+            }
+            None => sess.set_tcp_stream(address::connect(&hostname, port.parse()?, timeout)?),
+        }
+        sess.set_timeout(timeout.as_millis() as u32);
+        sess.handshake().context(
+            "Handshake (ssh) failed, does the remote host offer an ssh-ed25519 host key?",
+        )?;
+        // Only the connection is bounded, prompts answered during authentication take their time
+        sess.set_timeout(0);
+        let (pk_bytes, pk_type) = sess
             .host_key()
             .ok_or(anyhow!("No public key (ssh) found"))?;
+        if !matches!(pk_type, HostKeyType::Ed25519) {
+            bail!("Host public key is {pk_type:?}, expected an ssh-ed25519 key")
+        }
         let pk = PublicKey::from_bytes(pk_bytes)
             .context("Host public key parsing from bytes failed")?
             .to_openssh()
             .context("Host public key conversion to OpenSSH format failed")?;
 
-        local.ssh.update_knowing_hosts(&destination, &port, &pk)?;
-
-        let user = Input::with_theme(&ColorfulTheme::default())
-            .with_prompt("Enter ssh user:")
-            .default("nixos".to_string())
-            .allow_empty(false)
-            .show_default(true)
-            .interact_text()?;
-
-        let ssh_auth_opts = vec![AuthMethod::Agent, AuthMethod::Passwd];
-        let labels: Vec<String> = ssh_auth_opts
-            .iter()
-            .map(|ssh_auth| ssh_auth.to_string())
-            .collect();
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select an authentication method (ssh)?")
-            .items(&labels)
-            .interact()?;
-        match ssh_auth_opts
-            .get(selection)
-            .ok_or_else(|| anyhow!("Authentication method (ssh) not found"))?
-        {
-            AuthMethod::Agent => {
-                info!("🔸 Authentication (ssh) by agent");
-                sess.userauth_agent(&user)
-                    .context("Authentication (ssh) failed by agent")?
-            }
-            AuthMethod::Passwd => {
-                let mut i = 0;
-                loop {
-                    let password = Password::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Enter password (ssh):")
-                        .allow_empty_password(false)
-                        .interact()?;
-                    match sess.userauth_password(&user, &password) {
-                        Ok(_) => break,
-
-                        Err(_) => {
-                            i += 1;
-                            if i >= 3 {
-                                bail!("Authentication (ssh) failed: too many attempts");
-                            }
-
-                            if !helpers::ask_confirmation("Do you want to retry?")? {
-                                bail!("Authentication (ssh) failed by password")
-                            }
-                        }
-                    };
-                }
-
-                info!("🔸 Authenticating (ssh) by password");
-            }
-        }
+        known_hosts.verify_host_key(
+            &hostname,
+            &port,
+            &pk,
+            reinstall,
+            args.expect_fingerprint.as_deref(),
+        )?;
+
+        let user = match args.user.as_ref().or(ssh_config.user.as_ref()) {
+            Some(user) => user.to_string(),
+            None => prompt::input("user", "Enter ssh user:", Some("nixos"))?,
+        };
 
-        if !sess.authenticated() {
-            return Err(anyhow!("Authentication (ssh) failed"));
-        }
+        args.user = Some(user.to_string());
+        auth::authenticate(&sess, &user, args, credentials, "")?;
+        set_keepalive(&sess, args);
 
         info!("🔸 Remote host connected (via ssh) to {addr}");
-        Ok((sess, pk, user.to_string(), port))
+        Ok(Connection {
+            sess,
+            pk,
+            hostname,
+            user,
+            port,
+            jump,
+        })
     }
+}
 
-    pub fn run_command(&self, cmd: &str) -> Result<String> {
-        let mut channel = self
-            .ssh
-            .channel_session()
-            .context("Failed to establish a new session-based channel (ssh)")?;
-        channel.exec(cmd)?;
-        let mut stdout = String::new();
-        channel.read_to_string(&mut stdout)?;
-        let mut stderr = String::new();
-        channel.stderr().read_to_string(&mut stderr)?;
-        channel.wait_close()?;
-        let status = channel.exit_status()?;
-        if status != 0 {
-            bail!(
-                "Command (ssh) fail with exit status ({}) and stderr: \n{}",
-                status,
-                stderr
-            )
-        }
-        Ok(stdout)
-    }
+pub(super) fn connect_timeout(args: &RemoteArgs) -> Duration {
+    Duration::from_secs(args.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT))
+}
 
-    pub fn download_file(&self, remote_path: &str) -> Result<Vec<u8>> {
-        let (mut remote_file, _) = self.ssh.scp_recv(Path::new(remote_path))?;
-        let mut contents = Vec::new();
-        remote_file.read_to_end(&mut contents)?;
-        remote_file.send_eof()?;
-        remote_file.wait_eof()?;
-        remote_file.close()?;
-        remote_file.wait_close()?;
-        Ok(contents)
-    }
+/// Keepalives are sent while waiting on the session, they keep NAT mappings open.
+pub(super) fn set_keepalive(sess: &Session, args: &RemoteArgs) {
+    let interval = args.keepalive.unwrap_or(DEFAULT_KEEPALIVE);
+    sess.set_keepalive(false, interval);
 }
@@ -1,5 +1,109 @@
-#[derive(Default)]
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{cli::RemoteArgs, helpers::disk::DiskDevice};
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct State {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    dry_run: bool,
+    pub installer: Option<bool>,
+    pub installer_host: Option<String>,
+    pub host: Option<String>,
+    pub flake: Option<PathBuf>,
+    pub remote: RemoteArgs,
+    pub hardware_file: Option<String>,
+    pub disk_device: Option<DiskDevice>,
+    pub age_key: Option<String>,
     pub get_hardware_config: bool,
-    run_nixos_anywhere: bool,
+    pub select_disk_device: bool,
+    pub run_nixos_anywhere: bool,
+    pub reboot: bool,
+    pub add_age_key: bool,
+    pub rekey_secrets: bool,
+    pub run_nixos_rebuild: bool,
+}
+
+impl State {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(dirs2::data_local_dir()
+            .ok_or_else(|| anyhow!("Could not find local data directory"))?
+            .join("nix-bootstrap/state.json"))
+    }
+
+    pub fn open(path: Option<PathBuf>, resume: bool, dry_run: bool) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => Self::default_path()?,
+        };
+        if !resume || !path.exists() {
+            if resume {
+                warn!(
+                    "❗ No state found at {}, starting from scratch",
+                    path.display()
+                );
+            }
+            let state = Self {
+                path,
+                dry_run,
+                ..Default::default()
+            };
+            state.save()?;
+            return Ok(state);
+        }
+
+        info!("📂 Resume bootstrap from {}", path.display());
+        let contents =
+            fs::read_to_string(&path).context(format!("Reading {} failed", path.display()))?;
+        let mut state: Self = serde_json::from_str(&contents)
+            .context(format!("Parsing {} failed", path.display()))?;
+        state.path = path;
+        state.dry_run = dry_run;
+        Ok(state)
+    }
+
+    /// Refuse to resume the state of another host or destination.
+    pub fn check_target(&self, host: Option<&str>, destination: Option<&str>) -> Result<()> {
+        let targets = [
+            ("host", host, self.host.as_deref()),
+            (
+                "destination",
+                destination,
+                self.remote.destination.as_deref(),
+            ),
+        ];
+        for (name, given, saved) in targets {
+            if let (Some(given), Some(saved)) = (given, saved)
+                && given != saved
+            {
+                bail!(
+                    "State {} belongs to {name} {saved}, not {given}: drop --resume or use another --state-file",
+                    self.path.display()
+                )
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context(format!("Creating {} failed", parent.display()))?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)
+            .context(format!("Writing {} failed", self.path.display()))
+    }
+
+    pub fn update(&mut self, f: impl FnOnce(&mut Self)) -> Result<()> {
+        f(self);
+        self.save()
+    }
 }